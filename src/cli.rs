@@ -1,10 +1,60 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 use once_cell::sync::Lazy;
 
+use crate::dfpwm;
+
 pub static ARGS: Lazy<Args> = Lazy::new(Args::parse);
 
 #[derive(clap::Parser)]
 pub struct Args {
+    /// runs the server when left out
+    #[command(subcommand)]
+    pub command: Option<Command>,
     #[arg(short, long, default_value_t = 8080)]
     pub port: u16,
+    /// directory that `file:` urls are served from, local files can't be
+    /// streamed at all without it
+    #[arg(long)]
+    pub media_root: Option<PathBuf>,
+    /// lets a single client stream whatever is piped into the server through
+    /// the `stdin:` url
+    #[arg(long)]
+    pub stdin: bool,
+    /// yt-dlp binary to run when resolving urls
+    #[arg(long, default_value = "yt-dlp")]
+    pub ytdl_path: PathBuf,
+    /// extra argument passed to yt-dlp, can be given more than once
+    #[arg(long = "ytdl-arg", allow_hyphen_values = true)]
+    pub ytdl_args: Vec<String>,
+    /// how long yt-dlp gets to resolve a url, in seconds
+    #[arg(long, default_value_t = 30)]
+    pub ytdl_timeout: u64,
+    /// yt-dlp search prefix that `q=` searches go through, like `ytsearch5`
+    /// or `scsearch10`
+    #[arg(long, default_value = "ytsearch5")]
+    pub search_prefix: String,
+    /// how many resolved urls are kept around, 0 turns the cache off
+    #[arg(long, default_value_t = 64)]
+    pub cache_size: usize,
+    /// how long resolved urls are kept around for when they don't say when
+    /// they expire, in seconds
+    #[arg(long, default_value_t = 600)]
+    pub cache_ttl: u64,
+    /// sample rate audio is sent at, which should match whatever the
+    /// speakers play it back at
+    #[arg(long, default_value_t = dfpwm::SAMPLE_RATE)]
+    pub sample_rate: u32,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// decodes a .dfpwm file to WAV the way a speaker would play it
+    DecodeDfpwm {
+        input: PathBuf,
+        output: PathBuf,
+        #[arg(long, default_value_t = dfpwm::SAMPLE_RATE)]
+        sample_rate: u32,
+    },
 }
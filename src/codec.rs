@@ -0,0 +1,67 @@
+//! what audio gets sent as, DFPWM being what speakers were made for and
+//! PCM8 sounding a lot better for twice the bandwidth (or the same, at half
+//! the sample rate)
+
+use serde::{Deserialize, Serialize};
+
+use crate::dfpwm::DfpwmEncoder;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioCodec {
+    #[default]
+    Dfpwm,
+    /// signed 8 bit samples, which `speaker.playAudio` takes as is
+    Pcm8,
+    /// signed 8 bit samples at half the sample rate, the client has to
+    /// stretch them back out before playing them
+    Pcm8Half,
+}
+
+impl AudioCodec {
+    /// what audio has to be resampled to for this codec, `base` being the
+    /// rate the speakers play at
+    pub fn sample_rate(self, base: u32) -> u32 {
+        match self {
+            Self::Dfpwm | Self::Pcm8 => base,
+            Self::Pcm8Half => base / 2,
+        }
+    }
+}
+
+/// encoded audio, serialized as a plain list of numbers either way
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum EncodedAudio {
+    Dfpwm(Vec<u8>),
+    Pcm8(Vec<i8>),
+}
+
+/// keeps whatever state a codec needs between chunks, one of these is
+/// needed for every channel
+#[derive(Debug, Clone, Copy)]
+pub enum AudioEncoder {
+    Dfpwm(DfpwmEncoder),
+    Pcm8,
+}
+
+impl AudioEncoder {
+    pub fn new(codec: AudioCodec) -> Self {
+        match codec {
+            AudioCodec::Dfpwm => Self::Dfpwm(DfpwmEncoder::new()),
+            AudioCodec::Pcm8 | AudioCodec::Pcm8Half => Self::Pcm8,
+        }
+    }
+
+    pub fn encode(&mut self, samples: &[f32]) -> EncodedAudio {
+        match self {
+            Self::Dfpwm(encoder) => EncodedAudio::Dfpwm(encoder.encode(samples.iter().copied())),
+            Self::Pcm8 => EncodedAudio::Pcm8(
+                samples
+                    .iter()
+                    .map(|sample| (sample * 127.0).round().clamp(-128.0, 127.0) as i8)
+                    .collect(),
+            ),
+        }
+    }
+}
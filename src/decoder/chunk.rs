@@ -0,0 +1,166 @@
+//! cuts audio up into evenly sized chunks, since however much comes out of a
+//! single ffmpeg frame has nothing to do with what speakers like being fed
+
+use crate::frame::AudioFrame;
+
+/// what `speaker.playAudio` takes at most in one go
+pub const DEFAULT_CHUNK_SIZE: usize = 128 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct AudioChunker {
+    chunk_size: usize,
+    sample_rate: u32,
+    /// one buffer per channel, sized up to however many channels come in
+    buffer: Vec<Vec<f32>>,
+    /// timestamp of the first sample in the buffer
+    start: f64,
+}
+
+impl AudioChunker {
+    /// `chunk_size` gets rounded up to a whole number of bytes worth of DFPWM,
+    /// so that no chunk ends up padded halfway through the stream, and is
+    /// capped at [`DEFAULT_CHUNK_SIZE`] since speakers won't take any more
+    pub fn new(chunk_size: usize, sample_rate: u32) -> Self {
+        let chunk_size = chunk_size.clamp(1, DEFAULT_CHUNK_SIZE).next_multiple_of(8);
+        Self {
+            chunk_size,
+            sample_rate,
+            buffer: Vec::new(),
+            start: 0.0,
+        }
+    }
+
+    /// how long a full chunk lasts, in seconds
+    pub fn chunk_duration(&self) -> f64 {
+        self.chunk_size as f64 / f64::from(self.sample_rate)
+    }
+
+    /// throws away anything buffered, for when the input jumps somewhere else
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+    }
+
+    fn buffered(&self) -> usize {
+        self.buffer.first().map_or(0, Vec::len)
+    }
+
+    /// takes in the next frame, handing back every chunk that's been filled
+    /// up, each one stamped with when its first sample is due
+    pub fn push(&mut self, frame: &AudioFrame) -> Vec<AudioFrame> {
+        // frames pick up the timeline again whenever nothing's buffered,
+        // otherwise samples are assumed to follow on from the ones before
+        if self.buffered() == 0 {
+            self.start = frame.timestamp();
+        }
+        if self.buffer.len() != frame.channels().len() {
+            self.buffer = vec![Vec::with_capacity(self.chunk_size); frame.channels().len()];
+        }
+
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+        while offset < frame.sample_count() {
+            let take = (self.chunk_size - self.buffered()).min(frame.sample_count() - offset);
+            for (buffer, samples) in self.buffer.iter_mut().zip(frame.channels()) {
+                buffer.extend_from_slice(&samples[offset..offset + take]);
+            }
+            offset += take;
+
+            if self.buffered() == self.chunk_size {
+                chunks.extend(self.take_chunk());
+            }
+        }
+        chunks
+    }
+
+    /// hands out whatever's left over, for when the input ran out
+    pub fn flush(&mut self) -> Option<AudioFrame> {
+        self.take_chunk()
+    }
+
+    fn take_chunk(&mut self) -> Option<AudioFrame> {
+        if self.buffered() == 0 {
+            return None;
+        }
+        let chunk_size = self.chunk_size;
+        let channels = self
+            .buffer
+            .iter_mut()
+            .map(|buffer| std::mem::replace(buffer, Vec::with_capacity(chunk_size)))
+            .collect();
+        let chunk = AudioFrame::new(channels, self.start);
+        self.start += chunk.sample_count() as f64 / f64::from(self.sample_rate);
+        Some(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a stereo frame counting up from `first`, so every sample says where
+    /// it came from
+    fn frame(first: usize, len: usize, ts: f64) -> AudioFrame {
+        let samples: Vec<f32> = (first..first + len).map(|i| i as f32).collect();
+        AudioFrame::new(vec![samples.clone(), samples], ts)
+    }
+
+    #[test]
+    fn rounds_and_caps_chunk_size() {
+        assert_eq!(AudioChunker::new(0, 8).chunk_duration(), 1.0);
+        assert_eq!(AudioChunker::new(9, 8).chunk_duration(), 2.0);
+        let capped = AudioChunker::new(usize::MAX, 1);
+        assert_eq!(capped.chunk_duration(), DEFAULT_CHUNK_SIZE as f64);
+    }
+
+    #[test]
+    fn cuts_at_chunk_boundaries() {
+        let mut chunker = AudioChunker::new(8, 8);
+        assert!(chunker.push(&frame(0, 5, 0.0)).is_empty());
+
+        let chunks = chunker.push(&frame(5, 20, 0.625));
+        assert_eq!(chunks.len(), 3);
+        for (i, chunk) in chunks.iter().enumerate() {
+            let expected: Vec<f32> = (i * 8..(i + 1) * 8).map(|i| i as f32).collect();
+            assert_eq!(chunk.channels(), [expected.clone(), expected]);
+        }
+        // the last sample is still waiting on more
+        assert_eq!(chunker.flush().unwrap().channels()[0], [24.0]);
+    }
+
+    #[test]
+    fn timestamps_follow_on() {
+        let mut chunker = AudioChunker::new(8, 8);
+        let mut chunks = chunker.push(&frame(0, 12, 2.0));
+        // frames that don't quite line up get smoothed over as long as
+        // something's buffered
+        chunks.extend(chunker.push(&frame(12, 12, 3.6)));
+        chunks.extend(chunker.flush());
+
+        let timestamps: Vec<f64> = chunks.iter().map(AudioFrame::timestamp).collect();
+        assert_eq!(timestamps, [2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn flush_hands_out_leftovers_once() {
+        let mut chunker = AudioChunker::new(8, 8);
+        assert!(chunker.flush().is_none());
+        assert!(chunker.push(&frame(0, 3, 1.0)).is_empty());
+
+        let rest = chunker.flush().unwrap();
+        assert_eq!(rest.sample_count(), 3);
+        assert_eq!(rest.timestamp(), 1.0);
+        assert!(chunker.flush().is_none());
+    }
+
+    #[test]
+    fn reset_starts_from_next_frame() {
+        let mut chunker = AudioChunker::new(8, 8);
+        assert!(chunker.push(&frame(0, 5, 0.0)).is_empty());
+        chunker.reset();
+
+        let chunks = chunker.push(&frame(100, 8, 10.0));
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].timestamp(), 10.0);
+        assert_eq!(chunks[0].channels()[0][0], 100.0);
+    }
+}
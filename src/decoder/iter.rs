@@ -1,25 +1,203 @@
 use either::Either;
+use ffmpeg_next::{
+    error::{EAGAIN, EINVAL, ENOMEM},
+    format::context::Input,
+    Packet,
+};
 
 use crate::frame::{AudioFrame, VideoFrame};
 
 use super::{DecodeError, Decoder};
 
+/// whether a decoder turning down a packet means nothing after it is going to
+/// work either, as opposed to the packet itself just being broken
+fn is_fatal(error: &ffmpeg_next::Error) -> bool {
+    matches!(
+        error,
+        ffmpeg_next::Error::Eof
+            | ffmpeg_next::Error::Bug
+            | ffmpeg_next::Error::Bug2
+            | ffmpeg_next::Error::Other {
+                errno: EINVAL | ENOMEM
+            }
+    )
+}
+
+/// skips over packets the decoder rejected for being corrupt, which the ffmpeg
+/// cli does too, since a single bad packet (say from an HLS segment that
+/// failed to download) shouldn't end the whole stream
+fn skip_broken_packet(result: Result<(), ffmpeg_next::Error>) -> Result<(), ffmpeg_next::Error> {
+    match result {
+        Err(e) if !is_fatal(&e) => {
+            log::warn!("skipping packet the decoder couldn't use: {e}");
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// an input context along with how far into it reading has gotten
+struct PacketSource {
+    input: Input,
+    /// timestamp of the last packet read, in seconds
+    position: f64,
+    finished: bool,
+}
+
+impl PacketSource {
+    fn new(input: Input) -> Self {
+        Self {
+            input,
+            position: 0.0,
+            finished: false,
+        }
+    }
+
+    /// reads the next packet, `None` once the input ran out. ffmpeg-next's
+    /// own packet iterator retries forever on any error, which for a dropped
+    /// connection means spinning until the client gives up, so this only
+    /// retries when there's just nothing to read yet
+    fn next_packet(&mut self) -> Result<Option<Packet>, DecodeError> {
+        let mut packet = Packet::empty();
+        loop {
+            match packet.read(&mut self.input) {
+                Ok(()) => break,
+                Err(ffmpeg_next::Error::Eof) => {
+                    self.finished = true;
+                    return Ok(None);
+                }
+                Err(ffmpeg_next::Error::Other { errno: EAGAIN }) => continue,
+                Err(e) => return Err(DecodeError::FfmpegError(e)),
+            }
+        }
+
+        let time_base = self
+            .input
+            .stream(packet.stream())
+            .map(|stream| stream.time_base());
+        if let (Some(ts), Some(time_base)) = (packet.dts().or(packet.pts()), time_base) {
+            self.position = ts as f64 * f64::from(time_base);
+        }
+        Ok(Some(packet))
+    }
+
+    fn seek(&mut self, timestamp: f64) -> Result<(), DecodeError> {
+        // seek timestamps are in AV_TIME_BASE units, which is microseconds
+        let ts = (timestamp.max(0.0) * 1_000_000.0) as i64;
+        self.input.seek(ts, ..ts)?;
+        self.position = timestamp;
+        self.finished = false;
+        Ok(())
+    }
+}
+
 pub struct DecodeIter {
-    pub(super) input: ffmpeg_next::format::context::Input,
-    pub(super) decoders: Decoder,
+    input: PacketSource,
+    /// separate input that only audio is read from, for sources that keep
+    /// their audio and video apart
+    audio_input: Option<PacketSource>,
+    decoders: Decoder,
+    /// whether the input ran out and the decoders are being drained
+    flushing: bool,
+    /// frames from before this timestamp get thrown away, since seeking lands
+    /// on the keyframe before the requested position
+    skip_until: Option<f64>,
+}
+
+impl DecodeIter {
+    pub(super) fn new(input: Input, audio_input: Option<Input>, decoders: Decoder) -> Self {
+        Self {
+            input: PacketSource::new(input),
+            audio_input: audio_input.map(PacketSource::new),
+            decoders,
+            flushing: false,
+            skip_until: None,
+        }
+    }
+
+    /// jumps to the given timestamp (in seconds) and throws away anything
+    /// still buffered in the decoders
+    pub fn seek(&mut self, timestamp: f64) -> Result<(), DecodeError> {
+        self.input.seek(timestamp)?;
+        if let Some(audio_input) = self.audio_input.as_mut() {
+            audio_input.seek(timestamp)?;
+        }
+        self.decoders.flush();
+        self.flushing = false;
+        self.skip_until = Some(timestamp);
+        Ok(())
+    }
+
+    /// reads the next packet and hands it to the decoders, with two inputs
+    /// whichever one is further behind gets read from so they stay on the
+    /// same timeline
+    fn feed_decoders(&mut self) -> Result<(), DecodeError> {
+        let from_audio_input = self.audio_input.as_ref().is_some_and(|audio_input| {
+            !audio_input.finished
+                && (self.input.finished || audio_input.position <= self.input.position)
+        });
+
+        if from_audio_input {
+            let packet = match self.audio_input.as_mut() {
+                Some(audio_input) => audio_input.next_packet()?,
+                None => None,
+            };
+            if let Some(packet) = packet {
+                skip_broken_packet(self.decoders.send_audio_packet(&packet))?;
+            }
+        } else if let Some(packet) = self.input.next_packet()? {
+            if self.audio_input.is_some() {
+                skip_broken_packet(self.decoders.send_video_packet(&packet))?;
+            } else {
+                skip_broken_packet(self.decoders.send_packet(&packet))?;
+            }
+        }
+
+        let all_finished = self.input.finished
+            && self
+                .audio_input
+                .as_ref()
+                .is_none_or(|audio_input| audio_input.finished);
+        if all_finished {
+            self.flushing = true;
+            self.decoders.send_eof()?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Iterator for DecodeIter {
     type Item = Result<Either<VideoFrame, AudioFrame>, DecodeError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some((_, packet)) = self.input.packets().next() {
-            if self.decoders.send_packet(&packet).is_err() {
+        loop {
+            // hand out everything the decoders already have before feeding
+            // them more, since a single packet can turn into several frames
+            match self.decoders.try_receive_any_frame() {
+                Err(DecodeError::NoFramesYet) => (),
+                Err(DecodeError::EndOfStream) => return None,
+                Ok(frame) => {
+                    let ts = frame
+                        .as_ref()
+                        .either(VideoFrame::timestamp, AudioFrame::timestamp);
+                    match self.skip_until {
+                        Some(target) if ts < target => continue,
+                        Some(_) => self.skip_until = None,
+                        None => (),
+                    }
+                    return Some(Ok(frame));
+                }
+                Err(e) => return Some(Err(e)),
+            }
+
+            if self.flushing {
                 return None;
             }
-            return Some(self.decoders.try_receive_any_frame());
-        }
 
-        None
+            if let Err(e) = self.feed_decoders() {
+                return Some(Err(e));
+            }
+        }
     }
 }
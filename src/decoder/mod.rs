@@ -1,14 +1,20 @@
 use either::Either;
 use ffmpeg_next::{codec::Context, decoder, format::context::Input, Packet, Stream};
 use iter::DecodeIter;
+use pipeline::Pipeline;
+use queue::FrameQueue;
 use util::{audio_from_decoder, image_from_decoder};
 
 use crate::{
     dimensions::ResolutionHint,
-    frame::{AudioFrame, VideoFrame},
+    frame::{AudioFormat, AudioFrame, VideoFrame},
 };
 
+pub mod chunk;
 pub mod iter;
+mod pipeline;
+mod queue;
+pub mod rate;
 mod util;
 
 #[derive(Debug, thiserror::Error)]
@@ -17,10 +23,10 @@ pub enum DecodeError {
     FfmpegError(ffmpeg_next::Error),
     #[error("no frames yet to decode")]
     NoFramesYet,
+    #[error("decoder has been fully drained")]
+    EndOfStream,
     #[error("failed to convert frame to image")]
     ImageError,
-    #[error("audio frame had a length not divisible by 4")]
-    AudioFrameLength,
     #[error("there was no stream of the requested type: {0}")]
     NoSuchStream(&'static str),
 }
@@ -29,6 +35,7 @@ impl From<ffmpeg_next::Error> for DecodeError {
     fn from(value: ffmpeg_next::Error) -> Self {
         match value {
             ffmpeg_next::Error::Other { errno: 11 } => Self::NoFramesYet,
+            ffmpeg_next::Error::Eof => Self::EndOfStream,
             e => Self::FfmpegError(e),
         }
     }
@@ -39,10 +46,12 @@ pub enum Decoder {
         video_decoder: decoder::Video,
         video_stream_idx: usize,
         resolution_hint: ResolutionHint,
+        pipeline: Pipeline,
     },
     AudioOnly {
         audio_decoder: decoder::Audio,
         audio_stream_idx: usize,
+        pipeline: Pipeline,
     },
     Both {
         video_decoder: decoder::Video,
@@ -50,6 +59,8 @@ pub enum Decoder {
         resolution_hint: ResolutionHint,
         audio_decoder: decoder::Audio,
         audio_stream_idx: usize,
+        queue: FrameQueue,
+        pipeline: Pipeline,
     },
 }
 
@@ -59,22 +70,28 @@ impl Decoder {
         audio_stream: Stream,
         resolution_hint: ResolutionHint,
     ) -> Result<Self, DecodeError> {
-        let video_ctx = Context::from_parameters(video_stream.parameters())?;
-        let audio_ctx = Context::from_parameters(audio_stream.parameters())?;
+        let mut video_decoder = Context::from_parameters(video_stream.parameters())?.decoder();
+        video_decoder.set_packet_time_base(video_stream.time_base());
+        let mut audio_decoder = Context::from_parameters(audio_stream.parameters())?.decoder();
+        audio_decoder.set_packet_time_base(audio_stream.time_base());
         Ok(Self::Both {
-            video_decoder: video_ctx.decoder().video()?,
+            video_decoder: video_decoder.video()?,
             video_stream_idx: video_stream.index(),
             resolution_hint,
-            audio_decoder: audio_ctx.decoder().audio()?,
+            audio_decoder: audio_decoder.audio()?,
             audio_stream_idx: audio_stream.index(),
+            queue: FrameQueue::new(),
+            pipeline: Pipeline::new(),
         })
     }
 
     pub fn new_audio_only(audio_stream: Stream) -> Result<Self, DecodeError> {
-        let audio_ctx = Context::from_parameters(audio_stream.parameters())?;
+        let mut audio_decoder = Context::from_parameters(audio_stream.parameters())?.decoder();
+        audio_decoder.set_packet_time_base(audio_stream.time_base());
         Ok(Self::AudioOnly {
-            audio_decoder: audio_ctx.decoder().audio()?,
+            audio_decoder: audio_decoder.audio()?,
             audio_stream_idx: audio_stream.index(),
+            pipeline: Pipeline::new(),
         })
     }
 
@@ -82,73 +99,265 @@ impl Decoder {
         video_stream: Stream,
         resolution_hint: ResolutionHint,
     ) -> Result<Self, DecodeError> {
-        let video_ctx = Context::from_parameters(video_stream.parameters())?;
+        let mut video_decoder = Context::from_parameters(video_stream.parameters())?.decoder();
+        video_decoder.set_packet_time_base(video_stream.time_base());
         Ok(Self::VideoOnly {
-            video_decoder: video_ctx.decoder().video()?,
+            video_decoder: video_decoder.video()?,
             video_stream_idx: video_stream.index(),
             resolution_hint,
+            pipeline: Pipeline::new(),
         })
     }
 
     /// sends packet to approptiate decoder, otherwise discards it
     pub fn send_packet(&mut self, packet: &Packet) -> Result<(), ffmpeg_next::Error> {
-        let packet_stream_idx = packet.stream();
+        self.send_video_packet(packet)?;
+        self.send_audio_packet(packet)
+    }
+
+    /// sends packet to the video decoder if it belongs to the video stream,
+    /// otherwise discards it
+    pub fn send_video_packet(&mut self, packet: &Packet) -> Result<(), ffmpeg_next::Error> {
         match self {
             Self::VideoOnly {
                 video_decoder,
                 video_stream_idx,
                 resolution_hint: _,
+                pipeline: _,
+            }
+            | Self::Both {
+                video_decoder,
+                video_stream_idx,
+                resolution_hint: _,
+                audio_decoder: _,
+                audio_stream_idx: _,
+                queue: _,
+                pipeline: _,
             } => {
-                if packet_stream_idx != *video_stream_idx {
+                if packet.stream() != *video_stream_idx {
                     return Ok(());
                 }
                 video_decoder.send_packet(packet)
             }
+            Self::AudioOnly {
+                audio_decoder: _,
+                audio_stream_idx: _,
+                pipeline: _,
+            } => Ok(()),
+        }
+    }
+
+    /// sends packet to the audio decoder if it belongs to the audio stream,
+    /// otherwise discards it
+    pub fn send_audio_packet(&mut self, packet: &Packet) -> Result<(), ffmpeg_next::Error> {
+        match self {
             Self::AudioOnly {
                 audio_decoder,
                 audio_stream_idx,
+                pipeline: _,
+            }
+            | Self::Both {
+                video_decoder: _,
+                video_stream_idx: _,
+                resolution_hint: _,
+                audio_decoder,
+                audio_stream_idx,
+                queue: _,
+                pipeline: _,
             } => {
-                if packet_stream_idx != *audio_stream_idx {
+                if packet.stream() != *audio_stream_idx {
                     return Ok(());
                 }
                 audio_decoder.send_packet(packet)
             }
+            Self::VideoOnly {
+                video_decoder: _,
+                video_stream_idx: _,
+                resolution_hint: _,
+                pipeline: _,
+            } => Ok(()),
+        }
+    }
+
+    /// signals end of input to all decoders so they start handing out the frames
+    /// they still have buffered
+    pub fn send_eof(&mut self) -> Result<(), ffmpeg_next::Error> {
+        match self {
+            Self::VideoOnly {
+                video_decoder,
+                video_stream_idx: _,
+                resolution_hint: _,
+                pipeline: _,
+            } => video_decoder.send_eof(),
+            Self::AudioOnly {
+                audio_decoder,
+                audio_stream_idx: _,
+                pipeline: _,
+            } => audio_decoder.send_eof(),
             Self::Both {
                 video_decoder,
-                video_stream_idx,
+                video_stream_idx: _,
                 resolution_hint: _,
                 audio_decoder,
-                audio_stream_idx,
+                audio_stream_idx: _,
+                queue: _,
+                pipeline: _,
             } => {
-                if packet_stream_idx == *video_stream_idx {
-                    return video_decoder.send_packet(packet);
-                }
-                if packet_stream_idx == *audio_stream_idx {
-                    return audio_decoder.send_packet(packet);
-                }
-                Ok(())
+                video_decoder.send_eof()?;
+                audio_decoder.send_eof()
             }
         }
     }
 
+    /// throws away everything the decoders have buffered, for when the input
+    /// jumps somewhere else
+    pub fn flush(&mut self) {
+        match self {
+            Self::VideoOnly {
+                video_decoder,
+                video_stream_idx: _,
+                resolution_hint: _,
+                pipeline,
+            } => {
+                video_decoder.flush();
+                pipeline.reset();
+            }
+            Self::AudioOnly {
+                audio_decoder,
+                audio_stream_idx: _,
+                pipeline,
+            } => {
+                audio_decoder.flush();
+                pipeline.reset();
+            }
+            Self::Both {
+                video_decoder,
+                video_stream_idx: _,
+                resolution_hint: _,
+                audio_decoder,
+                audio_stream_idx: _,
+                queue,
+                pipeline,
+            } => {
+                video_decoder.flush();
+                audio_decoder.flush();
+                *queue = FrameQueue::new();
+                pipeline.reset();
+            }
+        }
+    }
+
+    /// receives the next frame from whichever decoder has one, for [`Self::Both`]
+    /// frames are handed out in the order of their timestamps
     pub fn try_receive_any_frame(&mut self) -> Result<Either<VideoFrame, AudioFrame>, DecodeError> {
         match self {
             Self::VideoOnly {
                 video_decoder: _,
                 video_stream_idx: _,
                 resolution_hint: _,
+                pipeline: _,
             } => self.try_receive_video_frame().map(Either::Left),
             Self::AudioOnly {
                 audio_decoder: _,
                 audio_stream_idx: _,
+                pipeline: _,
             } => self.try_receive_audio_frame().map(Either::Right),
             Self::Both {
                 video_decoder,
-                video_stream_idx,
+                video_stream_idx: _,
                 resolution_hint,
                 audio_decoder,
-                audio_stream_idx,
-            } => self.try_receive_video_frame().map(Either::Left),
+                audio_stream_idx: _,
+                queue,
+                pipeline,
+            } => {
+                loop {
+                    match image_from_decoder(video_decoder, resolution_hint, pipeline) {
+                        Ok(frame) => queue.push_video(frame),
+                        Err(DecodeError::NoFramesYet) => break,
+                        Err(DecodeError::EndOfStream) => {
+                            queue.finish_video();
+                            break;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                loop {
+                    match audio_from_decoder(audio_decoder, pipeline) {
+                        Ok(frame) => queue.push_audio(frame),
+                        Err(DecodeError::NoFramesYet) => break,
+                        Err(DecodeError::EndOfStream) => {
+                            queue.finish_audio();
+                            break;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                match queue.pop() {
+                    Some(frame) => Ok(frame),
+                    None if queue.is_finished() => Err(DecodeError::EndOfStream),
+                    None => Err(DecodeError::NoFramesYet),
+                }
+            }
+        }
+    }
+
+    /// resamples audio to `audio_format`, instead of the default
+    pub fn with_audio_format(mut self, audio_format: AudioFormat) -> Self {
+        match &mut self {
+            Self::VideoOnly {
+                video_decoder: _,
+                video_stream_idx: _,
+                resolution_hint: _,
+                pipeline,
+            }
+            | Self::AudioOnly {
+                audio_decoder: _,
+                audio_stream_idx: _,
+                pipeline,
+            }
+            | Self::Both {
+                video_decoder: _,
+                video_stream_idx: _,
+                resolution_hint: _,
+                audio_decoder: _,
+                audio_stream_idx: _,
+                queue: _,
+                pipeline,
+            } => pipeline.set_audio_format(audio_format),
+        }
+        self
+    }
+
+    /// how big video frames are going to come out, going by the size the
+    /// stream says it is. `None` if there's no video
+    pub fn output_dimensions(&self) -> Option<(u32, u32)> {
+        match self {
+            Self::VideoOnly {
+                video_decoder,
+                video_stream_idx: _,
+                resolution_hint,
+                pipeline: _,
+            }
+            | Self::Both {
+                video_decoder,
+                video_stream_idx: _,
+                resolution_hint,
+                audio_decoder: _,
+                audio_stream_idx: _,
+                queue: _,
+                pipeline: _,
+            } => {
+                let (width, height) =
+                    resolution_hint.get_target_res(video_decoder.width(), video_decoder.height());
+                // same clamping the scaler does
+                Some((width.max(1), height.max(1)))
+            }
+            Self::AudioOnly {
+                audio_decoder: _,
+                audio_stream_idx: _,
+                pipeline: _,
+            } => None,
         }
     }
 
@@ -158,10 +367,12 @@ impl Decoder {
                 video_decoder,
                 video_stream_idx: _,
                 resolution_hint,
-            } => image_from_decoder(video_decoder, resolution_hint),
+                pipeline,
+            } => image_from_decoder(video_decoder, resolution_hint, pipeline),
             Self::AudioOnly {
                 audio_decoder: _,
                 audio_stream_idx: _,
+                pipeline: _,
             } => Err(DecodeError::NoSuchStream("video")),
             Self::Both {
                 video_decoder,
@@ -169,7 +380,9 @@ impl Decoder {
                 resolution_hint,
                 audio_decoder: _,
                 audio_stream_idx: _,
-            } => image_from_decoder(video_decoder, resolution_hint),
+                queue: _,
+                pipeline,
+            } => image_from_decoder(video_decoder, resolution_hint, pipeline),
         }
     }
 
@@ -179,25 +392,33 @@ impl Decoder {
                 video_decoder: _,
                 video_stream_idx: _,
                 resolution_hint: _,
+                pipeline: _,
             } => Err(DecodeError::NoSuchStream("video")),
             Self::AudioOnly {
                 audio_decoder,
                 audio_stream_idx: _,
-            } => audio_from_decoder(audio_decoder),
+                pipeline,
+            } => audio_from_decoder(audio_decoder, pipeline),
             Self::Both {
                 video_decoder: _,
                 video_stream_idx: _,
                 resolution_hint: _,
                 audio_decoder,
                 audio_stream_idx: _,
-            } => audio_from_decoder(audio_decoder),
+                queue: _,
+                pipeline,
+            } => audio_from_decoder(audio_decoder, pipeline),
         }
     }
 
     pub fn into_frame_iter(self, input: Input) -> DecodeIter {
-        DecodeIter {
-            input,
-            decoders: self,
-        }
+        DecodeIter::new(input, None, self)
+    }
+
+    /// like [`Self::into_frame_iter`], but with audio coming from its own
+    /// input, the streams passed to [`Self::new_both`] have to come from
+    /// `video_input` and `audio_input` respectively
+    pub fn into_split_frame_iter(self, video_input: Input, audio_input: Input) -> DecodeIter {
+        DecodeIter::new(video_input, Some(audio_input), self)
     }
 }
@@ -0,0 +1,124 @@
+//! keeps the swscale and swresample contexts around between frames, only
+//! rebuilding them when whatever comes out of the decoder changes format
+//!
+//! the resampler in particular has to stick around, since it carries filter
+//! state and leftover samples from one frame over into the next
+
+use ffmpeg_next::{
+    format::{sample, Pixel, Sample},
+    frame::{Audio, Video},
+    software::{resampling, scaling},
+};
+
+use crate::{dimensions::ScaleFilter, frame::AudioFormat};
+
+pub const OUTPUT_PIXEL_FORMAT: Pixel = Pixel::RGB24;
+pub const OUTPUT_SAMPLE_FORMAT: Sample = Sample::F32(sample::Type::Planar);
+
+#[derive(Default)]
+pub struct Pipeline {
+    /// swscale doesn't let you read back which flags a context was made
+    /// with, so the filter is kept next to it
+    scaler: Option<(scaling::Context, ScaleFilter)>,
+    resampler: Option<resampling::Context>,
+    audio_format: AudioFormat,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// changes what audio gets resampled to from here on
+    pub fn set_audio_format(&mut self, audio_format: AudioFormat) {
+        if self.audio_format != audio_format {
+            self.audio_format = audio_format;
+            self.resampler = None;
+        }
+    }
+
+    /// drops any state carried over between frames, for when the input jumps
+    /// somewhere else
+    pub fn reset(&mut self) {
+        self.resampler = None;
+    }
+
+    /// gets a scaler for shrinking `frame` down to `width`x`height`, building
+    /// a new one if anything doesn't match what the current one was made for
+    pub fn scaler(
+        &mut self,
+        frame: &Video,
+        width: u32,
+        height: u32,
+        filter: ScaleFilter,
+    ) -> Result<&mut scaling::Context, ffmpeg_next::Error> {
+        // swscale refuses to scale down to nothing
+        let (width, height) = (width.max(1), height.max(1));
+
+        let up_to_date = self.scaler.as_ref().is_some_and(|(scaler, old_filter)| {
+            let input = scaler.input();
+            let output = scaler.output();
+            input.format == frame.format()
+                && input.width == frame.width()
+                && input.height == frame.height()
+                && output.width == width
+                && output.height == height
+                && *old_filter == filter
+        });
+
+        if !up_to_date {
+            log::debug!(
+                "building {:?} scaler for {:?} {}x{} -> {}x{}",
+                filter,
+                frame.format(),
+                frame.width(),
+                frame.height(),
+                width,
+                height
+            );
+            let scaler = scaling::Context::get(
+                frame.format(),
+                frame.width(),
+                frame.height(),
+                OUTPUT_PIXEL_FORMAT,
+                width,
+                height,
+                filter.into(),
+            )?;
+            self.scaler = Some((scaler, filter));
+        }
+
+        Ok(&mut self.scaler.as_mut().unwrap().0)
+    }
+
+    /// gets a resampler for converting `frame`, building a new one if the
+    /// frame doesn't match what the current one was made for
+    pub fn resampler(
+        &mut self,
+        frame: &Audio,
+    ) -> Result<&mut resampling::Context, ffmpeg_next::Error> {
+        let up_to_date = self.resampler.as_ref().is_some_and(|resampler| {
+            let input = resampler.input();
+            input.format == frame.format()
+                && input.channel_layout == frame.channel_layout()
+                && input.rate == frame.rate()
+        });
+
+        if !up_to_date {
+            log::debug!(
+                "building resampler for {:?} at {}Hz -> {:?} at {}Hz",
+                frame.format(),
+                frame.rate(),
+                self.audio_format.channels,
+                self.audio_format.sample_rate
+            );
+            self.resampler = Some(frame.resampler(
+                OUTPUT_SAMPLE_FORMAT,
+                self.audio_format.channels.layout(),
+                self.audio_format.sample_rate,
+            )?);
+        }
+
+        Ok(self.resampler.as_mut().unwrap())
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+
+use either::Either;
+
+use crate::frame::{AudioFrame, VideoFrame};
+
+/// how many frames of a single kind are allowed to pile up while waiting on
+/// the other stream before they get let through anyway
+const MAX_QUEUED: usize = 32;
+
+/// holds on to decoded frames from both streams so they can be handed out in
+/// the order of their timestamps
+#[derive(Debug, Default)]
+pub struct FrameQueue {
+    video: VecDeque<VideoFrame>,
+    audio: VecDeque<AudioFrame>,
+    video_finished: bool,
+    audio_finished: bool,
+}
+
+impl FrameQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_video(&mut self, frame: VideoFrame) {
+        self.video.push_back(frame);
+    }
+
+    pub fn push_audio(&mut self, frame: AudioFrame) {
+        self.audio.push_back(frame);
+    }
+
+    /// marks the video stream as drained, so audio frames no longer have to
+    /// wait on it
+    pub fn finish_video(&mut self) {
+        self.video_finished = true;
+    }
+
+    /// marks the audio stream as drained, so video frames no longer have to
+    /// wait on it
+    pub fn finish_audio(&mut self) {
+        self.audio_finished = true;
+    }
+
+    /// whether both streams are drained and every frame has been popped
+    pub fn is_finished(&self) -> bool {
+        self.video_finished && self.audio_finished && self.video.is_empty() && self.audio.is_empty()
+    }
+
+    /// pops the earliest frame, but only if there's a frame from the other
+    /// stream to compare it against (or the other stream is drained), since
+    /// otherwise an earlier one might still be on its way
+    pub fn pop(&mut self) -> Option<Either<VideoFrame, AudioFrame>> {
+        match (self.video.front(), self.audio.front()) {
+            (Some(video), Some(audio)) => {
+                if video.timestamp() <= audio.timestamp() {
+                    self.video.pop_front().map(Either::Left)
+                } else {
+                    self.audio.pop_front().map(Either::Right)
+                }
+            }
+            (Some(_), None) if self.audio_finished || self.video.len() > MAX_QUEUED => {
+                self.video.pop_front().map(Either::Left)
+            }
+            (None, Some(_)) if self.video_finished || self.audio.len() > MAX_QUEUED => {
+                self.audio.pop_front().map(Either::Right)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    fn video(ts: f64) -> VideoFrame {
+        VideoFrame::new(RgbImage::new(1, 1), ts)
+    }
+
+    fn audio(ts: f64) -> AudioFrame {
+        AudioFrame::new(vec![vec![0.0; 4]], ts)
+    }
+
+    fn timestamp(frame: &Either<VideoFrame, AudioFrame>) -> f64 {
+        frame
+            .as_ref()
+            .either(VideoFrame::timestamp, AudioFrame::timestamp)
+    }
+
+    #[test]
+    fn pops_in_timestamp_order() {
+        let mut queue = FrameQueue::new();
+        queue.push_video(video(0.0));
+        queue.push_video(video(0.5));
+        queue.push_audio(audio(0.25));
+        queue.push_audio(audio(1.0));
+
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|frame| (frame.is_left(), timestamp(&frame)))
+            .collect();
+        assert_eq!(order, [(true, 0.0), (false, 0.25), (true, 0.5)]);
+        // the last audio frame has to wait on video that might still come
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn waits_on_lagging_stream() {
+        let mut queue = FrameQueue::new();
+        queue.push_video(video(0.0));
+        queue.push_video(video(0.1));
+        assert!(queue.pop().is_none());
+
+        // audio that's behind still comes out first once it shows up
+        queue.push_audio(audio(0.05));
+        assert_eq!(queue.pop().map(|f| timestamp(&f)), Some(0.0));
+        assert_eq!(queue.pop().map(|f| timestamp(&f)), Some(0.05));
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn releases_past_max_queued() {
+        let mut queue = FrameQueue::new();
+        for i in 0..MAX_QUEUED {
+            queue.push_video(video(i as f64));
+        }
+        assert!(queue.pop().is_none());
+
+        queue.push_video(video(MAX_QUEUED as f64));
+        let frame = queue.pop().unwrap();
+        assert!(frame.is_left());
+        assert_eq!(timestamp(&frame), 0.0);
+        // back at the limit, so it goes back to waiting
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn drains_after_finishing() {
+        let mut queue = FrameQueue::new();
+        queue.push_audio(audio(0.0));
+        queue.push_audio(audio(0.1));
+        assert!(queue.pop().is_none());
+
+        queue.finish_video();
+        assert!(!queue.is_finished());
+        assert_eq!(queue.pop().map(|f| timestamp(&f)), Some(0.0));
+        assert_eq!(queue.pop().map(|f| timestamp(&f)), Some(0.1));
+        assert!(queue.pop().is_none());
+
+        queue.finish_audio();
+        assert!(queue.is_finished());
+    }
+
+    #[test]
+    fn drains_video_after_audio_finishes() {
+        let mut queue = FrameQueue::new();
+        queue.push_video(video(0.0));
+        queue.finish_audio();
+        assert!(queue.pop().unwrap().is_left());
+        queue.finish_video();
+        assert!(queue.is_finished());
+    }
+}
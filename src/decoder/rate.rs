@@ -0,0 +1,157 @@
+//! brings a video stream down to a lower frame rate by picking frames based
+//! on their timestamps
+
+use crate::frame::VideoFrame;
+
+#[derive(Debug, Clone)]
+pub struct FrameRateConverter {
+    /// time between output frames, in seconds
+    interval: f64,
+    blend: bool,
+    /// timestamp at which the next output frame is due
+    next_slot: Option<f64>,
+    /// last frame that came in, only kept around when blending
+    previous: Option<VideoFrame>,
+}
+
+impl FrameRateConverter {
+    pub fn new(fps: f64) -> Self {
+        Self {
+            interval: 1.0 / fps,
+            blend: false,
+            next_slot: None,
+            previous: None,
+        }
+    }
+
+    /// whether output frames should be mixed from the two source frames
+    /// around their slot instead of just taking the first one past it
+    pub fn with_blending(mut self, blend: bool) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    /// forgets about the current timeline, the next frame that comes in is
+    /// always let through
+    pub fn reset(&mut self) {
+        self.next_slot = None;
+        self.previous = None;
+    }
+
+    /// takes in the next source frame, returning a frame if one is due at the
+    /// output rate, anything that doesn't make the cut is dropped here so it
+    /// never gets any further down the line
+    pub fn push(&mut self, frame: VideoFrame) -> Option<VideoFrame> {
+        let ts = frame.timestamp();
+        let slot = *self.next_slot.get_or_insert(ts);
+
+        if ts < slot {
+            if self.blend {
+                self.previous = Some(frame);
+            }
+            return None;
+        }
+
+        // skip over any slots that went by without a frame
+        let missed = ((ts - slot) / self.interval).floor();
+        self.next_slot = Some(slot + (missed + 1.0) * self.interval);
+
+        if !self.blend {
+            return Some(frame);
+        }
+
+        let out = match self.previous.take() {
+            Some(previous) if missed == 0.0 && ts > previous.timestamp() => {
+                let weight = (slot - previous.timestamp()) / (ts - previous.timestamp());
+                previous.blend(&frame, weight as f32, slot)
+            }
+            _ => frame.clone(),
+        };
+        self.previous = Some(frame);
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    fn frame(ts: f64, value: u8) -> VideoFrame {
+        VideoFrame::new(RgbImage::from_pixel(2, 2, Rgb([value; 3])), ts)
+    }
+
+    /// timestamps of whatever makes it through when pushing frames at these
+    /// timestamps
+    fn kept(converter: &mut FrameRateConverter, timestamps: &[f64]) -> Vec<f64> {
+        timestamps
+            .iter()
+            .filter_map(|&ts| converter.push(frame(ts, 0)))
+            .map(|frame| frame.timestamp())
+            .collect()
+    }
+
+    #[test]
+    fn drops_down_to_output_rate() {
+        let mut converter = FrameRateConverter::new(10.0);
+        let input: Vec<f64> = (0..12).map(|i| i as f64 / 30.0).collect();
+        let output = kept(&mut converter, &input);
+        assert_eq!(output.len(), 4);
+        for (out, expected) in output.iter().zip([0.0, 0.1, 0.2, 0.3]) {
+            assert!((out - expected).abs() < 0.034, "{out} vs {expected}");
+        }
+    }
+
+    #[test]
+    fn skips_slots_across_gaps() {
+        let mut converter = FrameRateConverter::new(10.0);
+        assert_eq!(kept(&mut converter, &[0.0, 0.1]), [0.0, 0.1]);
+        // 0.2 through 0.4 went by without anything, the next slot is 0.5
+        assert_eq!(kept(&mut converter, &[0.45, 0.48, 0.5]), [0.45, 0.5]);
+    }
+
+    #[test]
+    fn reset_lets_next_frame_through() {
+        let mut converter = FrameRateConverter::new(10.0);
+        assert_eq!(kept(&mut converter, &[5.0]), [5.0]);
+        converter.reset();
+        // earlier than the next slot, but that slot's gone now
+        assert_eq!(kept(&mut converter, &[1.0, 1.05, 1.1]), [1.0, 1.1]);
+    }
+
+    #[test]
+    fn blends_frames_around_slot() {
+        let mut converter = FrameRateConverter::new(10.0).with_blending(true);
+        assert!(converter.push(frame(0.0, 0)).is_some());
+        assert!(converter.push(frame(0.06, 0)).is_none());
+
+        // the slot at 0.1 is halfway between 0.06 and 0.14
+        let out = converter.push(frame(0.14, 200)).unwrap();
+        assert_eq!(out.timestamp(), 0.1);
+        assert_eq!(out.get_pixel(0, 0), &Rgb([100; 3]));
+    }
+
+    #[test]
+    fn blend_leans_towards_closer_frame() {
+        let mut converter = FrameRateConverter::new(10.0).with_blending(true);
+        converter.push(frame(0.0, 0));
+        converter.push(frame(0.08, 0));
+
+        // the slot is a fifth of the way from 0.08 to 0.18
+        let out = converter.push(frame(0.18, 250)).unwrap();
+        assert_eq!(out.get_pixel(0, 0), &Rgb([50; 3]));
+    }
+
+    #[test]
+    fn no_blending_across_gaps() {
+        let mut converter = FrameRateConverter::new(10.0).with_blending(true);
+        converter.push(frame(0.0, 0));
+        converter.push(frame(0.05, 0));
+
+        // slots got skipped, so the previous frame is too far off to mix in
+        let out = converter.push(frame(0.35, 200)).unwrap();
+        assert_eq!(out.timestamp(), 0.35);
+        assert_eq!(out.get_pixel(0, 0), &Rgb([200; 3]));
+    }
+}
@@ -8,12 +8,13 @@ use crate::{
     frame::{AudioFrame, VideoFrame},
 };
 
-use super::DecodeError;
+use super::{pipeline::Pipeline, DecodeError};
 
 #[inline]
 pub fn image_from_decoder(
     decoder: &mut decoder::Video,
     resolution_hint: &ResolutionHint,
+    pipeline: &mut Pipeline,
 ) -> Result<VideoFrame, DecodeError> {
     let mut decoded = Video::empty();
     match decoder.receive_frame(&mut decoded) {
@@ -21,9 +22,8 @@ pub fn image_from_decoder(
             let (width, height) = resolution_hint.get_target_res(decoded.width(), decoded.height());
             Ok(VideoFrame::from_ffmpeg(
                 &decoded,
-                decoder.time_base().into(),
-                width,
-                height,
+                pipeline.scaler(&decoded, width, height, resolution_hint.filter())?,
+                decoder.packet_time_base().into(),
             )?)
         }
         Err(e) => Err(e)?,
@@ -31,12 +31,16 @@ pub fn image_from_decoder(
 }
 
 #[inline]
-pub fn audio_from_decoder(decoder: &mut decoder::Audio) -> Result<AudioFrame, DecodeError> {
+pub fn audio_from_decoder(
+    decoder: &mut decoder::Audio,
+    pipeline: &mut Pipeline,
+) -> Result<AudioFrame, DecodeError> {
     let mut decoded = Audio::empty();
     match decoder.receive_frame(&mut decoded) {
         Ok(_) => Ok(AudioFrame::from_ffmpeg(
             &decoded,
-            decoder.time_base().into(),
+            pipeline.resampler(&decoded)?,
+            decoder.packet_time_base().into(),
         )?),
         Err(e) => Err(e)?,
     }
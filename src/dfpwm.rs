@@ -1,7 +1,16 @@
-use bitvec::{order::Msb0, vec::BitVec};
+//! CC:Tweaked's flavour of DFPWM, see `cc.audio.dfpwm` for the reference
+//! implementation. bits are packed least significant first
+
+use bitvec::{order::Lsb0, vec::BitVec};
 
 const PREC: i32 = 10;
 
+/// how hard the decoder's low-pass filter smooths things out, out of 256
+const LPF_STRENGTH: i32 = 140;
+
+/// what CC:Tweaked's speaker plays DFPWM back at
+pub const SAMPLE_RATE: u32 = 48_000;
+
 #[derive(Debug, Default, Clone, Copy)]
 pub struct DfpwmEncoder {
     charge: i32,
@@ -18,36 +27,14 @@ impl DfpwmEncoder {
     pub fn encode(&mut self, samples: impl IntoIterator<Item = f32>) -> Vec<u8> {
         let iter = samples.into_iter();
         let size_hint = iter.size_hint();
-        let mut out = BitVec::<u8, Msb0>::with_capacity(size_hint.1.unwrap_or(size_hint.0));
+        let mut out = BitVec::<u8, Lsb0>::with_capacity(size_hint.1.unwrap_or(size_hint.0));
 
         for sample in iter {
             let level = (sample * 127.0).round().clamp(-128.0, 127.0) as i32;
 
             let current_bit = level > self.charge || (level == self.charge && self.charge == 127);
-            let target = if current_bit { 127 } else { -128 };
-
-            let mut next_charge = self.charge
-                + ((self.strength * (target - self.charge) + (1 << (PREC - 1))) >> PREC);
-            if next_charge == self.charge && next_charge != target {
-                next_charge += if current_bit { 1 } else { -1 };
-            }
-
-            let z = if current_bit == self.previous_bit {
-                1 << PREC
-            } else {
-                0
-            };
-            let mut next_strength = self.strength;
-            if self.strength != z {
-                next_strength += if current_bit == self.previous_bit {
-                    1
-                } else {
-                    -1
-                };
-            }
-            if next_strength < 2 << (PREC - 8) {
-                next_strength = 2 << (PREC - 8);
-            }
+            let (next_charge, next_strength) =
+                predict(self.charge, self.strength, self.previous_bit, current_bit);
 
             self.charge = next_charge;
             self.strength = next_strength;
@@ -59,3 +46,78 @@ impl DfpwmEncoder {
         out.into()
     }
 }
+
+/// works out where the predictor goes after `current_bit`, which has to come
+/// out exactly the same on both ends for anything to sound right
+#[inline]
+fn predict(charge: i32, strength: i32, previous_bit: bool, current_bit: bool) -> (i32, i32) {
+    let target = if current_bit { 127 } else { -128 };
+
+    let mut next_charge = charge + ((strength * (target - charge) + (1 << (PREC - 1))) >> PREC);
+    if next_charge == charge && next_charge != target {
+        next_charge += if current_bit { 1 } else { -1 };
+    }
+
+    // strength tops out one short of 1 << PREC, same as in cc.audio.dfpwm
+    let z = if current_bit == previous_bit {
+        (1 << PREC) - 1
+    } else {
+        0
+    };
+    let mut next_strength = strength;
+    if strength != z {
+        next_strength += if current_bit == previous_bit { 1 } else { -1 };
+    }
+    if next_strength < 2 << (PREC - 8) {
+        next_strength = 2 << (PREC - 8);
+    }
+
+    (next_charge, next_strength)
+}
+
+/// turns DFPWM back into samples the way CC:Tweaked's speakers do, anti-jerk
+/// and low-pass filter included
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DfpwmDecoder {
+    charge: i32,
+    strength: i32,
+    previous_bit: bool,
+    low_pass_charge: i32,
+}
+
+impl DfpwmDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// decodes into samples between -1 and 1, 8 for every byte
+    pub fn decode(&mut self, data: &[u8]) -> Vec<f32> {
+        let mut out = Vec::with_capacity(data.len() * 8);
+
+        for byte in data {
+            for i in 0..8 {
+                let current_bit = (byte >> i) & 1 != 0;
+                let (next_charge, next_strength) =
+                    predict(self.charge, self.strength, self.previous_bit, current_bit);
+
+                // anti-jerk: whenever the bit flips, only go halfway there
+                let blended_charge = if current_bit == self.previous_bit {
+                    next_charge
+                } else {
+                    (next_charge + self.charge + 1) >> 1
+                };
+
+                self.charge = next_charge;
+                self.strength = next_strength;
+                self.previous_bit = current_bit;
+
+                self.low_pass_charge +=
+                    ((blended_charge - self.low_pass_charge) * LPF_STRENGTH + 128) >> 8;
+
+                out.push(self.low_pass_charge as f32 / 127.0);
+            }
+        }
+
+        out
+    }
+}
@@ -1,31 +1,70 @@
+use ffmpeg_next::software::scaling;
+use serde::Deserialize;
+
 pub enum Dimension {
     Width,
     Height,
 }
 
+/// which filter swscale uses when shrinking frames down, roughly ordered from
+/// cheapest to best looking
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScaleFilter {
+    /// averages every source pixel that lands in an output pixel, which is
+    /// what you want most of the time when going from 1080p down to a monitor
+    #[default]
+    Area,
+    Bilinear,
+    Bicubic,
+    Lanczos,
+}
+
+impl From<ScaleFilter> for scaling::Flags {
+    fn from(value: ScaleFilter) -> Self {
+        match value {
+            ScaleFilter::Area => scaling::Flags::AREA,
+            ScaleFilter::Bilinear => scaling::Flags::BILINEAR,
+            ScaleFilter::Bicubic => scaling::Flags::BICUBIC,
+            ScaleFilter::Lanczos => scaling::Flags::LANCZOS,
+        }
+    }
+}
+
 pub enum ResolutionHint {
     FixedAspect {
         dimension: Dimension,
         size: u32,
+        filter: ScaleFilter,
     },
     FixedResolution {
         width: u32,
         height: u32,
+        filter: ScaleFilter,
     },
     Fit {
         width: u32,
         height: u32,
         pixel_aspect: f64,
+        filter: ScaleFilter,
     },
 }
 
 impl ResolutionHint {
     pub fn fixed_aspect(dimension: Dimension, size: u32) -> Self {
-        Self::FixedAspect { dimension, size }
+        Self::FixedAspect {
+            dimension,
+            size,
+            filter: ScaleFilter::default(),
+        }
     }
 
     pub fn fixed_resolution(width: u32, height: u32) -> Self {
-        Self::FixedResolution { width, height }
+        Self::FixedResolution {
+            width,
+            height,
+            filter: ScaleFilter::default(),
+        }
     }
 
     pub fn fit(width: u32, height: u32, pixel_aspect: f64) -> Self {
@@ -33,6 +72,50 @@ impl ResolutionHint {
             width,
             height,
             pixel_aspect,
+            filter: ScaleFilter::default(),
+        }
+    }
+
+    pub fn with_filter(mut self, new_filter: ScaleFilter) -> Self {
+        match &mut self {
+            Self::FixedAspect {
+                dimension: _,
+                size: _,
+                filter,
+            }
+            | Self::FixedResolution {
+                width: _,
+                height: _,
+                filter,
+            }
+            | Self::Fit {
+                width: _,
+                height: _,
+                pixel_aspect: _,
+                filter,
+            } => *filter = new_filter,
+        }
+        self
+    }
+
+    pub fn filter(&self) -> ScaleFilter {
+        match *self {
+            Self::FixedAspect {
+                dimension: _,
+                size: _,
+                filter,
+            }
+            | Self::FixedResolution {
+                width: _,
+                height: _,
+                filter,
+            }
+            | Self::Fit {
+                width: _,
+                height: _,
+                pixel_aspect: _,
+                filter,
+            } => filter,
         }
     }
 
@@ -42,6 +125,7 @@ impl ResolutionHint {
             ResolutionHint::FixedAspect {
                 dimension: Dimension::Width,
                 size,
+                filter: _,
             } => {
                 let aspect = original_width as f64 / original_height as f64;
                 (size, (size as f64 / aspect).round() as u32)
@@ -49,15 +133,21 @@ impl ResolutionHint {
             ResolutionHint::FixedAspect {
                 dimension: Dimension::Height,
                 size,
+                filter: _,
             } => {
                 let aspect = original_width as f64 / original_height as f64;
                 ((size as f64 * aspect).round() as u32, size)
             }
-            ResolutionHint::FixedResolution { width, height } => (width, height),
+            ResolutionHint::FixedResolution {
+                width,
+                height,
+                filter: _,
+            } => (width, height),
             ResolutionHint::Fit {
                 width,
                 height,
                 pixel_aspect,
+                filter: _,
             } => {
                 // FIXME: this is buggy
                 let aspect = original_width as f64 / original_height as f64;
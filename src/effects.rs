@@ -0,0 +1,334 @@
+//! processing that happens to audio after it's resampled and before it's
+//! encoded, mostly to make up for how badly DFPWM handles anything loud,
+//! quiet or high pitched
+
+/// something that messes with samples in place, keeping whatever state it
+/// needs from one call to the next
+pub trait AudioEffect: Send {
+    fn process(&mut self, samples: &mut [f32]);
+
+    /// forgets any state carried over, for when the input jumps somewhere else
+    fn reset(&mut self) {}
+}
+
+/// a bunch of effects run one after another, meant for a single channel
+#[derive(Default)]
+pub struct EffectChain {
+    effects: Vec<Box<dyn AudioEffect>>,
+}
+
+impl EffectChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, effect: impl AudioEffect + 'static) -> Self {
+        self.effects.push(Box::new(effect));
+        self
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for effect in &mut self.effects {
+            effect.process(samples);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for effect in &mut self.effects {
+            effect.reset();
+        }
+    }
+}
+
+/// coefficient for a one pole smoother that gets most of the way there in
+/// `seconds`
+fn smoothing(seconds: f32, sample_rate: u32) -> f32 {
+    1.0 - (-1.0 / (seconds * sample_rate as f32)).exp()
+}
+
+pub fn db_to_linear(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+/// multiplies everything by a fixed amount
+pub struct Gain {
+    factor: f32,
+}
+
+impl Gain {
+    pub fn new(factor: f32) -> Self {
+        Self { factor }
+    }
+}
+
+impl AudioEffect for Gain {
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            *sample *= self.factor;
+        }
+    }
+}
+
+/// slowly rides the gain so that everything ends up around the same
+/// loudness, which mostly means making quiet videos audible. a much quicker
+/// loudness estimate keeps it from boosting whatever comes after a quiet bit
+/// way past clipping while the slow one catches up
+pub struct Normalizer {
+    /// RMS level everything gets brought towards
+    target: f32,
+    /// RMS level nothing gets boosted past, even for a moment
+    ceiling: f32,
+    max_gain: f32,
+    /// how quickly the loudness estimate follows the input
+    coefficient: f32,
+    mean_square: f32,
+    /// same as the above, but fast enough to notice things getting loud
+    /// right away
+    fast_coefficient: f32,
+    fast_mean_square: f32,
+}
+
+impl Normalizer {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            target: 0.25,
+            ceiling: 0.5,
+            max_gain: 10.0,
+            coefficient: smoothing(3.0, sample_rate),
+            mean_square: 0.25 * 0.25,
+            fast_coefficient: smoothing(0.01, sample_rate),
+            fast_mean_square: 0.25 * 0.25,
+        }
+    }
+}
+
+impl AudioEffect for Normalizer {
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            let square = *sample * *sample;
+            self.mean_square += self.coefficient * (square - self.mean_square);
+            self.fast_mean_square += self.fast_coefficient * (square - self.fast_mean_square);
+            let rms = self.mean_square.sqrt().max(f32::EPSILON);
+            let fast_rms = self.fast_mean_square.sqrt().max(f32::EPSILON);
+            let gain = (self.target / rms)
+                .min(self.ceiling / fast_rms)
+                .min(self.max_gain);
+            *sample *= gain;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.mean_square = self.target * self.target;
+        self.fast_mean_square = self.target * self.target;
+    }
+}
+
+/// squashes anything louder than the threshold down, so loud bits don't clip
+/// and the DFPWM predictor doesn't get thrown around as much
+pub struct Compressor {
+    threshold_db: f32,
+    ratio: f32,
+    attack: f32,
+    release: f32,
+    /// level the input's been at lately, in dB
+    envelope_db: f32,
+}
+
+impl Compressor {
+    pub fn new(threshold_db: f32, ratio: f32, sample_rate: u32) -> Self {
+        Self {
+            threshold_db,
+            ratio: ratio.max(1.0),
+            attack: smoothing(0.005, sample_rate),
+            release: smoothing(0.1, sample_rate),
+            envelope_db: -120.0,
+        }
+    }
+}
+
+impl AudioEffect for Compressor {
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            let level_db = 20.0 * sample.abs().max(1e-6).log10();
+            let coefficient = if level_db > self.envelope_db {
+                self.attack
+            } else {
+                self.release
+            };
+            self.envelope_db += coefficient * (level_db - self.envelope_db);
+
+            let over = self.envelope_db - self.threshold_db;
+            if over > 0.0 {
+                *sample *= db_to_linear(-over * (1.0 - 1.0 / self.ratio));
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.envelope_db = -120.0;
+    }
+}
+
+/// one pole low-pass, takes the edge off of whatever DFPWM would turn into
+/// hiss anyways
+pub struct LowPass {
+    coefficient: f32,
+    previous: f32,
+}
+
+impl LowPass {
+    pub fn new(cutoff: f32, sample_rate: u32) -> Self {
+        let cutoff = cutoff.clamp(1.0, sample_rate as f32 / 2.0);
+        Self {
+            coefficient: 1.0 - (-std::f32::consts::TAU * cutoff / sample_rate as f32).exp(),
+            previous: 0.0,
+        }
+    }
+}
+
+impl AudioEffect for LowPass {
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            self.previous += self.coefficient * (*sample - self.previous);
+            *sample = self.previous;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.previous = 0.0;
+    }
+}
+
+/// boosts highs relative to lows, which makes up for the speaker's own
+/// low-pass filter dulling everything
+pub struct PreEmphasis {
+    coefficient: f32,
+    previous: f32,
+}
+
+impl PreEmphasis {
+    pub fn new(coefficient: f32) -> Self {
+        Self {
+            coefficient: coefficient.clamp(0.0, 1.0),
+            previous: 0.0,
+        }
+    }
+}
+
+impl AudioEffect for PreEmphasis {
+    fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples {
+            let input = *sample;
+            *sample = input - self.coefficient * self.previous;
+            self.previous = input;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.previous = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 8000;
+
+    fn sine(freq: f32, amplitude: f32, seconds: f32) -> Vec<f32> {
+        let len = (seconds * SAMPLE_RATE as f32) as usize;
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                amplitude * (std::f32::consts::TAU * freq * t).sin()
+            })
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |peak, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn normalizer_brings_quiet_audio_up() {
+        // the loudness estimate takes a good while to get all the way down
+        let mut samples = sine(200.0, 0.05, 30.0);
+        Normalizer::new(SAMPLE_RATE).process(&mut samples);
+        let settled = rms(&samples[samples.len() - SAMPLE_RATE as usize..]);
+        assert!((settled - 0.25).abs() < 0.02, "rms was {settled}");
+    }
+
+    #[test]
+    fn normalizer_backs_off_when_it_gets_loud() {
+        let mut samples = sine(200.0, 0.02, 5.0);
+        let onset = samples.len();
+        samples.extend(sine(200.0, 0.8, 1.0));
+        Normalizer::new(SAMPLE_RATE).process(&mut samples);
+
+        // a couple of milliseconds to catch up, and nothing clips after that
+        let after_onset = &samples[onset + SAMPLE_RATE as usize / 50..];
+        assert!(peak(after_onset) <= 1.0, "peak was {}", peak(after_onset));
+    }
+
+    #[test]
+    fn compressor_squashes_loud_parts() {
+        let mut compressor = Compressor::new(-20.0, 4.0, SAMPLE_RATE);
+        // a steady 0dB is 20dB over, which should come out 5dB over
+        let mut loud = vec![1.0; SAMPLE_RATE as usize];
+        compressor.process(&mut loud);
+        let expected = db_to_linear(-15.0);
+        assert!((loud[loud.len() - 1] - expected).abs() < 0.01);
+
+        compressor.reset();
+        let mut quiet = vec![0.01; SAMPLE_RATE as usize];
+        compressor.process(&mut quiet);
+        assert!(quiet.iter().all(|&s| s == 0.01));
+    }
+
+    #[test]
+    fn low_pass_keeps_lows_and_cuts_highs() {
+        let mut low = sine(20.0, 0.5, 1.0);
+        LowPass::new(500.0, SAMPLE_RATE).process(&mut low);
+        assert!(peak(&low[SAMPLE_RATE as usize / 2..]) > 0.49);
+
+        let mut high = sine(3000.0, 0.5, 1.0);
+        LowPass::new(500.0, SAMPLE_RATE).process(&mut high);
+        assert!(peak(&high[SAMPLE_RATE as usize / 2..]) < 0.15);
+    }
+
+    #[test]
+    fn pre_emphasis_boosts_highs_over_lows() {
+        let mut steady = vec![1.0; 100];
+        PreEmphasis::new(0.9).process(&mut steady);
+        assert!((steady[99] - 0.1).abs() < 1e-6);
+
+        // the highest frequency there is, flipping every sample
+        let mut alternating: Vec<f32> = (0..100)
+            .map(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        PreEmphasis::new(0.9).process(&mut alternating);
+        assert!((alternating[99].abs() - 1.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn reset_chain_starts_from_scratch() {
+        let mut chain = EffectChain::new()
+            .with(Normalizer::new(SAMPLE_RATE))
+            .with(Compressor::new(-10.0, 4.0, SAMPLE_RATE))
+            .with(LowPass::new(1000.0, SAMPLE_RATE))
+            .with(PreEmphasis::new(0.5));
+        let input = sine(440.0, 0.5, 0.1);
+
+        let mut first = input.clone();
+        chain.process(&mut first);
+        chain.process(&mut sine(50.0, 0.01, 1.0));
+        chain.reset();
+        let mut again = input.clone();
+        chain.process(&mut again);
+        assert_eq!(first, again);
+    }
+}
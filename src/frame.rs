@@ -3,10 +3,84 @@
 
 use std::ops::{Deref, DerefMut};
 
-use ffmpeg_next::frame::{Audio, Video};
+use ffmpeg_next::{
+    frame::{Audio, Video},
+    software::{resampling, scaling},
+    ChannelLayout,
+};
 use image::RgbImage;
+use serde::Deserialize;
+
+use crate::{decoder::DecodeError, dfpwm};
+
+/// which speakers audio gets split up between
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioChannels {
+    #[default]
+    Mono,
+    Stereo,
+    /// 5.1, with the surround speakers off to the sides
+    Surround,
+}
+
+impl AudioChannels {
+    pub fn layout(self) -> ChannelLayout {
+        match self {
+            Self::Mono => ChannelLayout::MONO,
+            Self::Stereo => ChannelLayout::STEREO,
+            Self::Surround => ChannelLayout::_5POINT1,
+        }
+    }
 
-use crate::decoder::DecodeError;
+    /// what each channel is called, in the same order ffmpeg puts them in
+    pub fn names(self) -> &'static [&'static str] {
+        match self {
+            Self::Mono => &["mono"],
+            Self::Stereo => &["left", "right"],
+            Self::Surround => &[
+                "front_left",
+                "front_right",
+                "center",
+                "lfe",
+                "side_left",
+                "side_right",
+            ],
+        }
+    }
+
+    pub fn count(self) -> usize {
+        self.names().len()
+    }
+}
+
+/// what audio gets resampled to before it's encoded and sent off, the same
+/// one has to make it to both ends of that
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: AudioChannels,
+}
+
+impl Default for AudioFormat {
+    fn default() -> Self {
+        Self::new(dfpwm::SAMPLE_RATE)
+    }
+}
+
+impl AudioFormat {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            channels: AudioChannels::default(),
+        }
+    }
+
+    pub fn with_channels(mut self, channels: AudioChannels) -> Self {
+        self.channels = channels;
+        self
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct VideoFrame {
@@ -29,24 +103,32 @@ impl DerefMut for VideoFrame {
 }
 
 impl VideoFrame {
+    pub fn new(image: RgbImage, timestamp: f64) -> Self {
+        Self { timestamp, image }
+    }
+
+    /// converts a decoded frame, the output size is whatever `scaler` was
+    /// set up to scale to
     pub fn from_ffmpeg(
         decoded: &Video,
+        scaler: &mut scaling::Context,
         time_base: f64,
-        width: u32,
-        height: u32,
     ) -> Result<Self, DecodeError> {
-        let mut converter = decoded.converter(ffmpeg_next::format::Pixel::RGB24)?;
-
         let mut converted = Video::empty();
-        converter.run(decoded, &mut converted)?;
-
-        let buf = Vec::from(converted.data(0));
+        scaler.run(decoded, &mut converted)?;
+
+        // rows are usually padded out for alignment, so they have to be copied
+        // over one by one
+        let width = converted.width() as usize;
+        let height = converted.height() as usize;
+        let stride = converted.stride(0);
+        let mut buf = Vec::with_capacity(width * height * 3);
+        for row in converted.data(0).chunks(stride).take(height) {
+            buf.extend_from_slice(&row[..width * 3]);
+        }
         let image = image::RgbImage::from_raw(converted.width(), converted.height(), buf)
             .ok_or(DecodeError::ImageError)?;
 
-        let image =
-            image::imageops::resize(&image, width, height, image::imageops::FilterType::Nearest);
-
         let ts = decoded.pts().unwrap() as f64 * time_base;
 
         Ok(VideoFrame {
@@ -55,6 +137,31 @@ impl VideoFrame {
         })
     }
 
+    /// mixes this frame with `other`, `weight` being how much of `other` ends
+    /// up in the result
+    pub fn blend(&self, other: &VideoFrame, weight: f32, timestamp: f64) -> VideoFrame {
+        if self.image.dimensions() != other.image.dimensions() {
+            return VideoFrame {
+                timestamp,
+                image: other.image.clone(),
+            };
+        }
+
+        let weight = weight.clamp(0.0, 1.0);
+        let buf = self
+            .image
+            .as_raw()
+            .iter()
+            .zip(other.image.as_raw())
+            .map(|(&a, &b)| (a as f32 * (1.0 - weight) + b as f32 * weight).round() as u8)
+            .collect();
+
+        VideoFrame {
+            timestamp,
+            image: RgbImage::from_raw(self.image.width(), self.image.height(), buf).unwrap(),
+        }
+    }
+
     pub fn timestamp(&self) -> f64 {
         self.timestamp
     }
@@ -66,46 +173,62 @@ impl VideoFrame {
 
 #[derive(Debug, Clone)]
 pub struct AudioFrame {
-    samples: Vec<f32>,
+    /// samples for every channel, all of them the same length
+    channels: Vec<Vec<f32>>,
     timestamp: f64,
 }
 
 impl Deref for AudioFrame {
-    type Target = Vec<f32>;
+    type Target = Vec<Vec<f32>>;
 
     fn deref(&self) -> &Self::Target {
-        &self.samples
+        &self.channels
     }
 }
 
 impl DerefMut for AudioFrame {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.samples
+        &mut self.channels
     }
 }
 
 impl AudioFrame {
-    pub fn from_ffmpeg(decoded: &Audio, time_base: f64) -> Result<Self, DecodeError> {
-        let mut resampler = decoded.resampler(
-            ffmpeg_next::format::Sample::F32(ffmpeg_next::format::sample::Type::Planar),
-            ffmpeg_next::ChannelLayout::MONO,
-            44100,
-        )?;
-        let mut resampled = Audio::empty();
-        resampler.run(decoded, &mut resampled)?;
-
-        let buf = resampled.data(0);
-        if buf.len() % 4 != 0 {
-            return Err(DecodeError::AudioFrameLength);
+    /// samples for each channel at whatever rate audio got resampled to,
+    /// `timestamp` being when the first of them is due
+    pub fn new(channels: Vec<Vec<f32>>, timestamp: f64) -> Self {
+        Self {
+            channels,
+            timestamp,
         }
-        // HEEEEEEEEELP
-        let samples =
-            unsafe { core::slice::from_raw_parts::<f32>(buf.as_ptr() as _, buf.len() / 4) };
+    }
+
+    pub fn from_ffmpeg(
+        decoded: &Audio,
+        resampler: &mut resampling::Context,
+        time_base: f64,
+    ) -> Result<Self, DecodeError> {
+        // make room for everything this frame turns into plus whatever the
+        // resampler still had left over from the previous one, otherwise the
+        // leftovers just keep piling up inside of it
+        let output = *resampler.output();
+        let pending = resampler
+            .delay()
+            .map_or(0, |delay| delay.output.max(0) as usize);
+        let capacity = (decoded.samples() as u64 * output.rate as u64)
+            .div_ceil(decoded.rate().max(1) as u64) as usize
+            + pending;
+        let mut resampled = Audio::new(output.format, capacity, output.channel_layout);
+        resampler.run(decoded, &mut resampled)?;
 
         let ts = decoded.pts().unwrap() as f64 * time_base;
 
+        // the output is planar, so every channel gets a plane of its own
+        let channels = (0..resampled.planes())
+            .map(|plane| resampled.plane::<f32>(plane).to_vec())
+            .collect();
+
         Ok(Self {
-            samples: samples.into(),
+            channels,
             timestamp: ts,
         })
     }
@@ -114,7 +237,12 @@ impl AudioFrame {
         self.timestamp
     }
 
-    pub fn samples(&self) -> &[f32] {
-        &self.samples
+    pub fn channels(&self) -> &[Vec<f32>] {
+        &self.channels
+    }
+
+    /// how many samples there are in each channel
+    pub fn sample_count(&self) -> usize {
+        self.channels.first().map_or(0, Vec::len)
     }
 }
@@ -0,0 +1,394 @@
+//! follows HLS playlists by hand and pipes the segments into ffmpeg, since
+//! ffmpeg's own HLS demuxer gives up on live streams at the first hiccup
+
+use std::{
+    collections::HashMap,
+    io::{PipeReader, Read, Write},
+    os::fd::AsRawFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use m3u8_rs::{KeyMethod, MasterPlaylist, MediaPlaylist, Playlist, VariantStream};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use url::Url;
+
+/// how many times in a row a playlist or segment can fail to load before
+/// giving up on it
+const MAX_RETRIES: u32 = 5;
+
+/// how many segments from the end of a live playlist playback starts at, the
+/// spec says not to start any closer than 3 target durations to the end
+const LIVE_EDGE_SEGMENTS: usize = 3;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum HlsError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Url(#[from] url::ParseError),
+    #[error("failed to parse playlist at {0}")]
+    Parse(Url),
+    #[error("master playlist has no playable variants")]
+    NoVariants,
+    /// something ffmpeg's own HLS demuxer can deal with but we can't, so it
+    /// should be left to that instead
+    #[error("playlist uses {0}, which can't be followed by hand")]
+    Unsupported(&'static str),
+}
+
+pub fn is_hls_url(url: &Url) -> bool {
+    let path = url.path();
+    path.ends_with(".m3u8") || path.ends_with(".m3u")
+}
+
+/// whether a variant only carries audio, going by its codecs
+fn is_audio_only(variant: &VariantStream) -> bool {
+    variant.codecs.as_deref().is_some_and(|codecs| {
+        codecs.split(',').map(str::trim).all(|codec| {
+            ["mp4a", "opus", "ac-3", "ec-3", "flac"]
+                .iter()
+                .any(|audio| codec.starts_with(audio))
+        })
+    })
+}
+
+/// picks the smallest variant that still covers `width`x`height`, falling
+/// back to the biggest one there is if none of them do, or to the cheapest
+/// one if the playlist doesn't list resolutions at all. with `audio_only`
+/// it's the cheapest audio only variant, or the cheapest one of all if there
+/// aren't any
+pub fn select_variant(
+    master: &MasterPlaylist,
+    width: u32,
+    height: u32,
+    audio_only: bool,
+) -> Option<&VariantStream> {
+    let playable = || master.variants.iter().filter(|variant| !variant.is_i_frame);
+    if audio_only {
+        return playable()
+            .filter(|variant| is_audio_only(variant))
+            .min_by_key(|variant| variant.bandwidth)
+            .or_else(|| playable().min_by_key(|variant| variant.bandwidth));
+    }
+
+    let candidates = || {
+        master
+            .variants
+            .iter()
+            .filter(|variant| !variant.is_i_frame && !is_audio_only(variant))
+    };
+
+    let covering = candidates()
+        .filter(|variant| {
+            variant
+                .resolution
+                .as_ref()
+                .is_some_and(|res| res.width >= u64::from(width) && res.height >= u64::from(height))
+        })
+        .min_by_key(|variant| variant.bandwidth);
+    let biggest = || {
+        candidates()
+            .filter(|variant| variant.resolution.is_some())
+            .max_by_key(|variant| variant.bandwidth)
+    };
+    let cheapest = || candidates().min_by_key(|variant| variant.bandwidth);
+
+    covering.or_else(biggest).or_else(cheapest)
+}
+
+/// index of the first segment in `playlist` that hasn't been played yet,
+/// `last_sequence` being the media sequence number of the last one that was
+pub fn first_new_segment(playlist: &MediaPlaylist, last_sequence: Option<u64>) -> usize {
+    match last_sequence {
+        // the stream got restarted, so it's picked up again as if it was
+        // just opened
+        Some(_) if has_restarted(playlist, last_sequence) => first_new_segment(playlist, None),
+        // if we fell so far behind that it already slid out of the playlist,
+        // this just ends up starting from the oldest one still in there
+        Some(last) => (last + 1).saturating_sub(playlist.media_sequence) as usize,
+        None if playlist.end_list => 0,
+        None => playlist.segments.len().saturating_sub(LIVE_EDGE_SEGMENTS),
+    }
+}
+
+/// whether the playlist went way back behind the last segment that was
+/// played, which happens when the stream gets restarted. a playlist that's
+/// only a reload or so behind is just a stale copy from some CDN edge, and
+/// the next reload sorts that out by itself
+fn has_restarted(playlist: &MediaPlaylist, last_sequence: Option<u64>) -> bool {
+    let len = playlist.segments.len() as u64;
+    last_sequence.is_some_and(|last| playlist.media_sequence < last.saturating_sub(len))
+}
+
+/// makes sure every segment can just be downloaded and passed along as is,
+/// encrypted segments and byte ranges are left to ffmpeg
+pub fn check_supported(playlist: &MediaPlaylist) -> Result<(), HlsError> {
+    for segment in &playlist.segments {
+        if segment
+            .key
+            .as_ref()
+            .is_some_and(|key| key.method != KeyMethod::None)
+        {
+            return Err(HlsError::Unsupported("encryption"));
+        }
+        if segment.byte_range.is_some()
+            || segment
+                .map
+                .as_ref()
+                .is_some_and(|map| map.byte_range.is_some())
+        {
+            return Err(HlsError::Unsupported("byte ranges"));
+        }
+    }
+    Ok(())
+}
+
+/// the read end of a pipe that segments get written into, open it with
+/// ffmpeg through [`HlsInput::ffmpeg_path`]
+pub struct HlsInput {
+    reader: PipeReader,
+    /// set whenever the stream starts over on a new timeline
+    discontinuity: Arc<AtomicBool>,
+    _follower: JoinHandle<()>,
+}
+
+impl HlsInput {
+    /// starts following the playlist at `url` in the background, picking a
+    /// variant that suits `width`x`height` (or `audio_only`) if it's a master
+    /// playlist, every request is sent with `http_headers`. the first media
+    /// playlist is loaded right away, so [`HlsError::Unsupported`] comes back
+    /// from here if the stream should be opened by ffmpeg instead
+    pub fn spawn(
+        url: Url,
+        width: u32,
+        height: u32,
+        audio_only: bool,
+        http_headers: &HashMap<String, String>,
+    ) -> Result<Self, HlsError> {
+        let headers: HeaderMap = http_headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((
+                    HeaderName::try_from(name.as_str()).ok()?,
+                    HeaderValue::try_from(value.as_str()).ok()?,
+                ))
+            })
+            .collect();
+        let client = reqwest::blocking::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .default_headers(headers)
+            .build()?;
+
+        let discontinuity = Arc::new(AtomicBool::new(false));
+        let follower = HlsFollower {
+            client,
+            url,
+            width,
+            height,
+            audio_only,
+            discontinuity: discontinuity.clone(),
+        };
+        let (media_url, playlist) = follower.open()?;
+        check_supported(&playlist)?;
+
+        let (reader, writer) = std::io::pipe()?;
+        let handle = std::thread::spawn(move || match follower.run(media_url, playlist, writer) {
+            Ok(()) => log::debug!("reached the end of the HLS stream"),
+            Err(HlsError::Io(e)) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+                log::debug!("ffmpeg stopped reading the HLS stream")
+            }
+            Err(e) => log::error!("stopped following HLS stream: {e}"),
+        });
+
+        Ok(Self {
+            reader,
+            discontinuity,
+            _follower: handle,
+        })
+    }
+
+    /// path that makes ffmpeg read from the pipe
+    pub fn ffmpeg_path(&self) -> String {
+        format!("pipe:{}", self.reader.as_raw_fd())
+    }
+
+    /// whether the stream restarted or hit an `EXT-X-DISCONTINUITY` since
+    /// this was last asked, meaning timestamps are about to jump somewhere
+    /// else. whatever's still in the pipe comes before the jump though
+    pub fn take_discontinuity(&self) -> bool {
+        self.discontinuity.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// reads the segments straight out of the pipe, for anything that isn't
+/// ffmpeg
+impl Read for HlsInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+struct HlsFollower {
+    client: reqwest::blocking::Client,
+    url: Url,
+    width: u32,
+    height: u32,
+    audio_only: bool,
+    discontinuity: Arc<AtomicBool>,
+}
+
+impl HlsFollower {
+    fn fetch(&self, url: &Url) -> Result<Vec<u8>, HlsError> {
+        let resp = self.client.get(url.clone()).send()?.error_for_status()?;
+        Ok(resp.bytes()?.to_vec())
+    }
+
+    fn fetch_with_retries(&self, url: &Url) -> Result<Vec<u8>, HlsError> {
+        let mut attempt = 0;
+        loop {
+            match self.fetch(url) {
+                Ok(bytes) => return Ok(bytes),
+                Err(e) if attempt >= MAX_RETRIES => return Err(e),
+                Err(e) => {
+                    attempt += 1;
+                    log::warn!("failed to fetch {url} (attempt {attempt}): {e}");
+                    std::thread::sleep(Duration::from_millis(250 * 2u64.pow(attempt)));
+                }
+            }
+        }
+    }
+
+    fn fetch_playlist(&self, url: &Url) -> Result<Playlist, HlsError> {
+        let bytes = self.fetch(url)?;
+        m3u8_rs::parse_playlist_res(&bytes).map_err(|_| HlsError::Parse(url.clone()))
+    }
+
+    fn fetch_media_playlist(&self, url: &Url) -> Result<MediaPlaylist, HlsError> {
+        match self.fetch_playlist(url)? {
+            Playlist::MediaPlaylist(playlist) => Ok(playlist),
+            Playlist::MasterPlaylist(_) => Err(HlsError::Parse(url.clone())),
+        }
+    }
+
+    /// resolves the configured url down to a media playlist, going through
+    /// the master playlist if there is one, along with where it came from
+    fn open(&self) -> Result<(Url, MediaPlaylist), HlsError> {
+        match self.fetch_playlist(&self.url)? {
+            Playlist::MasterPlaylist(master) => {
+                let variant = select_variant(&master, self.width, self.height, self.audio_only)
+                    .ok_or(HlsError::NoVariants)?;
+                log::debug!(
+                    "picked HLS variant {:?} at {} bps",
+                    variant.resolution,
+                    variant.bandwidth
+                );
+                let media_url = self.url.join(&variant.uri)?;
+                let playlist = self.fetch_media_playlist(&media_url)?;
+                Ok((media_url, playlist))
+            }
+            Playlist::MediaPlaylist(playlist) => Ok((self.url.clone(), playlist)),
+        }
+    }
+
+    /// loads the media playlist again, retrying a few times before giving up
+    /// on it
+    fn reload(&self, media_url: &mut Url) -> Result<MediaPlaylist, HlsError> {
+        let mut failures = 0;
+        loop {
+            match self.fetch_media_playlist(media_url) {
+                Ok(playlist) => return Ok(playlist),
+                Err(e) if failures >= MAX_RETRIES => return Err(e),
+                Err(e) => {
+                    failures += 1;
+                    log::warn!("failed to reload playlist (attempt {failures}): {e}");
+                    std::thread::sleep(Duration::from_secs(failures as u64));
+                    // variant urls tend to be signed and expire, so going back
+                    // through the master playlist gets us a fresh one
+                    if let Ok((url, playlist)) = self.open() {
+                        *media_url = url;
+                        return Ok(playlist);
+                    }
+                }
+            }
+        }
+    }
+
+    /// writes out every segment from `playlist` on, reloading it from
+    /// `media_url` until it ends
+    fn run(
+        self,
+        mut media_url: Url,
+        mut playlist: MediaPlaylist,
+        mut out: impl Write,
+    ) -> Result<(), HlsError> {
+        let mut last_sequence: Option<u64> = None;
+        let mut last_map: Option<Url> = None;
+        let mut fetched_at = Instant::now();
+
+        loop {
+            // it's too late to hand this over to ffmpeg now
+            check_supported(&playlist)?;
+            let restarted = has_restarted(&playlist, last_sequence);
+            if restarted {
+                log::warn!("HLS playlist went backwards, starting over from its live edge");
+                last_map = None;
+            }
+
+            let start = first_new_segment(&playlist, last_sequence);
+            let had_new_segments = start < playlist.segments.len();
+            for (idx, segment) in playlist.segments.iter().enumerate().skip(start) {
+                // nothing needs to know about the very first segment being
+                // on a timeline of its own
+                let new_timeline = (restarted && idx == start) || segment.discontinuity;
+                if new_timeline && last_sequence.is_some() {
+                    log::debug!("HLS stream starts a new timeline at {}", segment.uri);
+                    self.discontinuity.store(true, Ordering::SeqCst);
+                }
+
+                // fragmented mp4 streams need their init segment before anything
+                // else, and again whenever it changes
+                if let Some(map) = &segment.map {
+                    let map_url = media_url.join(&map.uri)?;
+                    if last_map.as_ref() != Some(&map_url) {
+                        out.write_all(&self.fetch_with_retries(&map_url)?)?;
+                        last_map = Some(map_url);
+                    }
+                }
+
+                let segment_url = media_url.join(&segment.uri)?;
+                match self.fetch_with_retries(&segment_url) {
+                    // a failed write means ffmpeg hung up on us, which is fine
+                    Ok(bytes) => out.write_all(&bytes)?,
+                    Err(e) => log::warn!("skipping segment {segment_url}: {e}"),
+                }
+                last_sequence = Some(playlist.media_sequence + idx as u64);
+            }
+
+            if playlist.end_list {
+                return Ok(());
+            }
+
+            // the spec says to wait a whole target duration between reloads,
+            // or half of one if the playlist didn't change
+            let target_duration = Duration::from_secs_f64(playlist.target_duration as f64);
+            let wait = if had_new_segments {
+                target_duration
+            } else {
+                target_duration / 2
+            };
+            std::thread::sleep(wait.saturating_sub(fetched_at.elapsed()));
+
+            fetched_at = Instant::now();
+            playlist = self.reload(&mut media_url)?;
+        }
+    }
+}
@@ -1,8 +1,16 @@
 pub mod cli;
+pub mod codec;
 pub mod decoder;
 pub mod dfpwm;
 pub mod dimensions;
+pub mod effects;
 pub mod frame;
+pub mod hls;
+pub mod metadata;
+pub mod pacer;
 pub mod palette;
+pub mod resolver;
+pub mod source;
+pub mod wav;
 pub mod web;
 pub mod ytdl;
@@ -1,4 +1,15 @@
-use cc_streaming::{cli::ARGS, web::stream};
+use std::{io::Write, sync::Arc, time::Duration};
+
+use cc_streaming::{
+    cli::{Command, ARGS},
+    dfpwm::DfpwmDecoder,
+    resolver::{
+        cache::{CachingResolver, ResolverCache},
+        AutoResolver, SourceResolver,
+    },
+    wav::write_wav,
+    web::{invalidate_cache, stream, StreamConfig},
+};
 
 const DEFAULT_LEVEL: &str = {
     #[cfg(debug_assertions)]
@@ -14,10 +25,48 @@ const DEFAULT_LEVEL: &str = {
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or(DEFAULT_LEVEL));
+
+    if let Some(Command::DecodeDfpwm {
+        input,
+        output,
+        sample_rate,
+    }) = &ARGS.command
+    {
+        let samples = DfpwmDecoder::new().decode(&std::fs::read(input)?);
+        let mut out = std::io::BufWriter::new(std::fs::File::create(output)?);
+        write_wav(&mut out, &samples, *sample_rate)?;
+        out.flush()?;
+        log::info!(
+            "decoded {} samples into {}",
+            samples.len(),
+            output.display()
+        );
+        return Ok(());
+    }
+
     ffmpeg_next::init().unwrap();
 
-    actix_web::HttpServer::new(|| {
-        actix_web::App::new().route("/stream", actix_web::web::get().to(stream))
+    let cache = Arc::new(ResolverCache::new(
+        ARGS.cache_size,
+        Duration::from_secs(ARGS.cache_ttl),
+    ));
+    let resolver: Arc<dyn SourceResolver> = Arc::new(CachingResolver::new(
+        AutoResolver::from_args(&ARGS),
+        cache.clone(),
+    ));
+
+    let config = StreamConfig::from_args(&ARGS);
+
+    actix_web::HttpServer::new(move || {
+        actix_web::App::new()
+            .app_data(actix_web::web::Data::from(resolver.clone()))
+            .app_data(actix_web::web::Data::new(config))
+            .app_data(actix_web::web::Data::from(cache.clone()))
+            .route("/stream", actix_web::web::get().to(stream))
+            .route(
+                "/cache/invalidate",
+                actix_web::web::post().to(invalidate_cache),
+            )
     })
     .bind((std::net::Ipv6Addr::UNSPECIFIED, ARGS.port))
     .unwrap()
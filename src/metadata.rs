@@ -0,0 +1,68 @@
+//! what's being played, for the client to show a title bar and a progress
+//! bar with
+
+use ffmpeg_next::format::context::Input;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MediaInfo {
+    pub title: Option<String>,
+    /// in seconds, not there for live streams
+    pub duration: Option<f64>,
+    pub uploader: Option<String>,
+    pub chapters: Vec<Chapter>,
+}
+
+/// same shape as yt-dlp's chapters, so they can be taken from there as is
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chapter {
+    pub start_time: f64,
+    pub end_time: f64,
+    pub title: Option<String>,
+}
+
+impl MediaInfo {
+    /// whatever the container says about itself, which for files is usually
+    /// a lot more than for anything streamed
+    pub fn from_input(input: &Input) -> Self {
+        let metadata = input.metadata();
+        let tag = |key: &str| metadata.get(key).map(str::to_string);
+
+        // container durations are in AV_TIME_BASE units (microseconds), and
+        // AV_NOPTS_VALUE when unknown
+        let duration = (input.duration() > 0).then(|| input.duration() as f64 / 1_000_000.0);
+
+        let chapters = input
+            .chapters()
+            .map(|chapter| {
+                let time_base = f64::from(chapter.time_base());
+                Chapter {
+                    start_time: chapter.start() as f64 * time_base,
+                    end_time: chapter.end() as f64 * time_base,
+                    title: chapter.metadata().get("title").map(str::to_string),
+                }
+            })
+            .collect();
+
+        Self {
+            title: tag("title"),
+            duration,
+            uploader: tag("artist").or_else(|| tag("album_artist")),
+            chapters,
+        }
+    }
+
+    /// fills in anything missing from `self` with what `other` has
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            title: self.title.or(other.title),
+            duration: self.duration.or(other.duration),
+            uploader: self.uploader.or(other.uploader),
+            chapters: if self.chapters.is_empty() {
+                other.chapters
+            } else {
+                self.chapters
+            },
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! keeps outgoing frames in step with the media clock instead of sending them
+//! as fast as they can be decoded
+
+use std::time::{Duration, Instant};
+
+/// how far behind schedule a frame can be before it counts as late
+const DEFAULT_LATE_THRESHOLD: Duration = Duration::from_millis(100);
+
+/// how far off from the wall clock a timestamp can be before it's taken as
+/// the stream jumping somewhere else, rather than just running behind
+pub const DEFAULT_MAX_DRIFT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pace {
+    /// the frame was held back until it was due
+    OnTime,
+    /// the frame's time had already passed, so it's probably not worth sending
+    Late,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pacer {
+    /// wall clock time at which the first frame was released, along with its
+    /// timestamp
+    origin: Option<(Instant, f64)>,
+    late_threshold: Duration,
+    max_drift: Duration,
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pacer {
+    pub fn new() -> Self {
+        Self::with_late_threshold(DEFAULT_LATE_THRESHOLD)
+    }
+
+    pub fn with_late_threshold(late_threshold: Duration) -> Self {
+        Self {
+            origin: None,
+            late_threshold,
+            max_drift: DEFAULT_MAX_DRIFT,
+        }
+    }
+
+    /// how far a timestamp can jump ahead of (or back behind) the clock
+    /// before the timeline starts over from it, this has to be more than the
+    /// longest gap there ever is between two timestamps
+    pub fn with_max_drift(mut self, max_drift: Duration) -> Self {
+        self.max_drift = max_drift;
+        self
+    }
+
+    /// forgets the current timeline, the next frame will be released right
+    /// away and become the new reference point
+    pub fn reset(&mut self) {
+        self.origin = None;
+    }
+
+    /// blocks until the frame with the given timestamp (in seconds) is due,
+    /// returning right away if it's already past it. timestamps that are way
+    /// off from where the clock is at (restarted streams, ad breaks and the
+    /// like) start a new timeline instead of sleeping through the gap or
+    /// making everything after them late
+    pub fn wait(&mut self, timestamp: f64) -> Pace {
+        let now = Instant::now();
+        let (start, first_ts) = *self.origin.get_or_insert((now, timestamp));
+        let offset = timestamp - first_ts;
+        let expected = now.saturating_duration_since(start).as_secs_f64();
+        if (offset - expected).abs() > self.max_drift.as_secs_f64() {
+            log::debug!(
+                "timestamp {timestamp:.3}s is {:.3}s off from the clock, starting over",
+                offset - expected
+            );
+            self.origin = Some((now, timestamp));
+            return Pace::OnTime;
+        }
+        let deadline = start + Duration::from_secs_f64(offset.max(0.0));
+
+        if now < deadline {
+            std::thread::sleep(deadline - now);
+            Pace::OnTime
+        } else if now - deadline > self.late_threshold {
+            Pace::Late
+        } else {
+            Pace::OnTime
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pacer() -> Pacer {
+        Pacer::with_late_threshold(Duration::from_millis(50))
+            .with_max_drift(Duration::from_millis(500))
+    }
+
+    /// how long `wait` blocked for, along with what it said
+    fn timed_wait(pacer: &mut Pacer, timestamp: f64) -> (Duration, Pace) {
+        let started = Instant::now();
+        let pace = pacer.wait(timestamp);
+        (started.elapsed(), pace)
+    }
+
+    #[test]
+    fn holds_frames_until_due() {
+        let mut pacer = pacer();
+        assert_eq!(pacer.wait(10.0), Pace::OnTime);
+
+        let (waited, pace) = timed_wait(&mut pacer, 10.1);
+        assert_eq!(pace, Pace::OnTime);
+        assert!(waited >= Duration::from_millis(90), "waited {waited:?}");
+    }
+
+    #[test]
+    fn reports_late_frames() {
+        let mut pacer = pacer();
+        pacer.wait(0.0);
+        std::thread::sleep(Duration::from_millis(150));
+
+        let (waited, pace) = timed_wait(&mut pacer, 0.05);
+        assert_eq!(pace, Pace::Late);
+        assert!(waited < Duration::from_millis(20), "waited {waited:?}");
+        // just slightly behind is still fine
+        assert_eq!(pacer.wait(0.12), Pace::OnTime);
+    }
+
+    #[test]
+    fn starts_over_after_jumping_ahead() {
+        let mut pacer = pacer();
+        pacer.wait(0.0);
+
+        let (waited, pace) = timed_wait(&mut pacer, 3600.0);
+        assert_eq!(pace, Pace::OnTime);
+        assert!(waited < Duration::from_millis(20), "waited {waited:?}");
+
+        // the new timeline is the one that counts now
+        let (waited, pace) = timed_wait(&mut pacer, 3600.1);
+        assert_eq!(pace, Pace::OnTime);
+        assert!(waited >= Duration::from_millis(90), "waited {waited:?}");
+    }
+
+    #[test]
+    fn starts_over_after_jumping_back() {
+        let mut pacer = pacer();
+        pacer.wait(3600.0);
+
+        assert_eq!(pacer.wait(0.0), Pace::OnTime);
+        let (waited, pace) = timed_wait(&mut pacer, 0.1);
+        assert_eq!(pace, Pace::OnTime);
+        assert!(waited >= Duration::from_millis(90), "waited {waited:?}");
+    }
+}
@@ -0,0 +1,475 @@
+//! remembers what urls resolved to, since going through yt-dlp takes a few
+//! seconds every time and a bunch of computers tend to watch the same thing
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use url::Url;
+
+use crate::{source::Source, ytdl::PlaylistEntry};
+
+use super::{Resolution, ResolveError, SourceResolver};
+
+/// how long a url has to stay valid for after being handed out, so that it
+/// doesn't expire halfway through whatever's being played
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30 * 60);
+
+/// what gets resolved depends on how big it's going to be shown (and whether
+/// it's shown at all), since that decides which formats yt-dlp picks
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    url: Url,
+    width: u32,
+    height: u32,
+    audio_only: bool,
+}
+
+struct CacheEntry {
+    resolution: Resolution,
+    expires_at: Instant,
+}
+
+pub struct ResolverCache {
+    entries: Mutex<HashMap<CacheKey, CacheEntry>>,
+    capacity: usize,
+    /// how long entries last when their urls don't say when they expire
+    default_ttl: Duration,
+}
+
+impl ResolverCache {
+    /// a cache holding at most `capacity` entries, which disables it if it's
+    /// 0
+    pub fn new(capacity: usize, default_ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            capacity,
+            default_ttl,
+        }
+    }
+
+    fn get(&self, key: &CacheKey) -> Option<Resolution> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.expires_at > Instant::now() => Some(entry.resolution.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, key: CacheKey, resolution: Resolution) {
+        if self.capacity == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let expires_at = match resolution_expiry(&resolution) {
+            Some(expiry) => match expiry
+                .duration_since(SystemTime::now())
+                .ok()
+                .and_then(|left| left.checked_sub(EXPIRY_MARGIN))
+            {
+                Some(left) => now + left,
+                // it'd be gone before anyone got to use it
+                None => return,
+            },
+            None => now + self.default_ttl,
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.expires_at > now);
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            // whatever's closest to expiring is the least useful to keep
+            let soonest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(key, _)| key.clone());
+            if let Some(soonest) = soonest {
+                entries.remove(&soonest);
+            }
+        }
+        entries.insert(
+            key,
+            CacheEntry {
+                resolution,
+                expires_at,
+            },
+        );
+    }
+
+    /// forgets everything `url` resolved to, at any size, returning how many
+    /// entries that was
+    pub fn invalidate(&self, url: &Url) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|key, _| key.url != *url);
+        before - entries.len()
+    }
+
+    /// forgets everything, returning how many entries that was
+    pub fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.clear();
+        count
+    }
+}
+
+/// wraps another resolver, only asking it about urls that aren't cached yet
+pub struct CachingResolver<R> {
+    inner: R,
+    cache: Arc<ResolverCache>,
+    /// lookups that are still going, so that everyone asking for the same
+    /// thing at once waits on the first one instead of all going to `inner`
+    pending: Mutex<HashMap<CacheKey, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl<R: SourceResolver> CachingResolver<R> {
+    pub fn new(inner: R, cache: Arc<ResolverCache>) -> Self {
+        Self {
+            inner,
+            cache,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// forgets about the lookup for `key` once nobody's waiting on it anymore
+    fn finish_pending(&self, key: &CacheKey, lock: Arc<tokio::sync::Mutex<()>>) {
+        let mut pending = self.pending.lock().unwrap();
+        // one's in the map and the other one is ours
+        if Arc::strong_count(&lock) <= 2 {
+            pending.remove(key);
+        }
+    }
+}
+
+impl<R: SourceResolver> SourceResolver for CachingResolver<R> {
+    fn resolve<'a>(
+        &'a self,
+        url: &'a Url,
+        width: u32,
+        height: u32,
+        audio_only: bool,
+    ) -> BoxFuture<'a, Result<Resolution, ResolveError>> {
+        async move {
+            let key = CacheKey {
+                url: url.clone(),
+                width,
+                height,
+                audio_only,
+            };
+            if let Some(resolution) = self.cache.get(&key) {
+                log::debug!("using cached resolution for {url}");
+                return Ok(resolution);
+            }
+
+            let lock = self
+                .pending
+                .lock()
+                .unwrap()
+                .entry(key.clone())
+                .or_default()
+                .clone();
+            let guard = lock.lock().await;
+            // whoever held the lock before might've just resolved it
+            let resolution = match self.cache.get(&key) {
+                Some(resolution) => {
+                    log::debug!("using resolution of {url} that was just looked up");
+                    Ok(resolution)
+                }
+                None => {
+                    let resolution = self.inner.resolve(url, width, height, audio_only).await;
+                    if let Ok(resolution) = &resolution {
+                        if is_cacheable(resolution) {
+                            self.cache.insert(key.clone(), resolution.clone());
+                        }
+                    }
+                    resolution
+                }
+            };
+            drop(guard);
+            self.finish_pending(&key, lock);
+            resolution
+        }
+        .boxed()
+    }
+
+    fn invalidate(&self, url: &Url) {
+        let removed = self.cache.invalidate(url);
+        if removed > 0 {
+            log::info!("dropped {removed} cached resolutions of {url}, since they didn't work");
+        }
+        self.inner.invalidate(url);
+    }
+
+    fn search<'a>(
+        &'a self,
+        query: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<PlaylistEntry>, ResolveError>> {
+        self.inner.search(query)
+    }
+}
+
+/// only things that came off the network are worth caching, local files are
+/// quick to look up and stdin has to be claimed every single time
+fn is_cacheable(resolution: &Resolution) -> bool {
+    match resolution {
+        Resolution::Media(resolved) => {
+            matches!(resolved.source, Source::Direct(_) | Source::Split { .. })
+        }
+        Resolution::Playlist(_) => true,
+    }
+}
+
+/// when the earliest of the urls in `resolution` stops working, as far as
+/// the urls themselves say
+fn resolution_expiry(resolution: &Resolution) -> Option<SystemTime> {
+    match resolution {
+        Resolution::Media(resolved) => match &resolved.source {
+            Source::Direct(url) => url_expiry(url),
+            Source::Split { video, audio } => match (url_expiry(video), url_expiry(audio)) {
+                (Some(video), Some(audio)) => Some(video.min(audio)),
+                (video, audio) => video.or(audio),
+            },
+            _ => None,
+        },
+        Resolution::Playlist(_) => None,
+    }
+}
+
+/// signed urls tend to carry their expiry as a unix timestamp, either in an
+/// `expire` (youtube) or `Expires` (cloudfront) query parameter or, for
+/// youtube's HLS manifests, as an `/expire/<timestamp>/` bit of the path
+fn url_expiry(url: &Url) -> Option<SystemTime> {
+    let from_query = url
+        .query_pairs()
+        .find(|(name, _)| name == "expire" || name == "Expires")
+        .and_then(|(_, value)| value.parse::<u64>().ok());
+    let from_path = || {
+        let mut segments = url.path_segments()?;
+        segments.find(|segment| *segment == "expire")?;
+        segments.next()?.parse::<u64>().ok()
+    };
+
+    from_query
+        .or_else(from_path)
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// resolves everything to itself after a little while, counting how
+    /// many times it had to
+    #[derive(Default)]
+    struct CountingResolver {
+        calls: AtomicUsize,
+    }
+
+    impl SourceResolver for CountingResolver {
+        fn resolve<'a>(
+            &'a self,
+            url: &'a Url,
+            _width: u32,
+            _height: u32,
+            _audio_only: bool,
+        ) -> BoxFuture<'a, Result<Resolution, ResolveError>> {
+            async move {
+                self.calls.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(Source::Direct(url.clone()).into())
+            }
+            .boxed()
+        }
+    }
+
+    fn unix_now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// a url that says it expires `hours` from now
+    fn expiring_url(name: &str, hours: u64) -> Url {
+        let expire = unix_now() + hours * 60 * 60;
+        Url::parse(&format!("https://example.com/{name}?expire={expire}")).unwrap()
+    }
+
+    fn key(url: &Url) -> CacheKey {
+        CacheKey {
+            url: url.clone(),
+            width: 0,
+            height: 0,
+            audio_only: false,
+        }
+    }
+
+    fn resolution(url: &Url) -> Resolution {
+        Source::Direct(url.clone()).into()
+    }
+
+    #[test]
+    fn reads_expiry_from_query() {
+        let url =
+            Url::parse("https://rr1.googlevideo.com/videoplayback?expire=1700000000&id=1").unwrap();
+        assert_eq!(
+            url_expiry(&url),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+
+        let url = Url::parse("https://d1.cloudfront.net/video.mp4?Expires=1700000001").unwrap();
+        assert_eq!(
+            url_expiry(&url),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_001))
+        );
+    }
+
+    #[test]
+    fn reads_expiry_from_path() {
+        let url = Url::parse(
+            "https://manifest.googlevideo.com/api/manifest/hls_variant/expire/1700000002/ei/abc/file/index.m3u8",
+        )
+        .unwrap();
+        assert_eq!(
+            url_expiry(&url),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_002))
+        );
+    }
+
+    #[test]
+    fn no_expiry_without_timestamp() {
+        for url in [
+            "https://example.com/video.mp4",
+            "https://example.com/video.mp4?expire=soon",
+            "https://example.com/expire/video.mp4",
+        ] {
+            assert_eq!(url_expiry(&Url::parse(url).unwrap()), None, "{url}");
+        }
+    }
+
+    #[test]
+    fn split_expires_with_earliest() {
+        let video = expiring_url("video", 3);
+        let audio = expiring_url("audio", 2);
+        let resolution: Resolution = Source::Split {
+            video: video.clone(),
+            audio: audio.clone(),
+        }
+        .into();
+        assert_eq!(resolution_expiry(&resolution), url_expiry(&audio));
+    }
+
+    #[test]
+    fn skips_urls_about_to_expire() {
+        let cache = ResolverCache::new(4, Duration::from_secs(3600));
+        let url = Url::parse(&format!(
+            "https://example.com/video?expire={}",
+            unix_now() + 60
+        ))
+        .unwrap();
+        cache.insert(key(&url), resolution(&url));
+        assert!(cache.get(&key(&url)).is_none());
+    }
+
+    #[test]
+    fn evicts_whatever_expires_first() {
+        let cache = ResolverCache::new(2, Duration::from_secs(600));
+        let soon = expiring_url("soon", 2);
+        let later = expiring_url("later", 4);
+        let latest = expiring_url("latest", 6);
+
+        cache.insert(key(&later), resolution(&later));
+        cache.insert(key(&soon), resolution(&soon));
+        cache.insert(key(&latest), resolution(&latest));
+
+        assert!(cache.get(&key(&soon)).is_none());
+        assert!(cache.get(&key(&later)).is_some());
+        assert!(cache.get(&key(&latest)).is_some());
+    }
+
+    #[test]
+    fn default_ttl_only_covers_urls_without_expiry() {
+        // same as the --cache-ttl default
+        let cache = ResolverCache::new(2, Duration::from_secs(600));
+        let expiring = expiring_url("expiring", 6);
+        let plain = Url::parse("https://example.com/plain").unwrap();
+        cache.insert(key(&expiring), resolution(&expiring));
+        cache.insert(key(&plain), resolution(&plain));
+
+        let entries = cache.entries.lock().unwrap();
+        let left = |url: &Url| {
+            entries[&key(url)]
+                .expires_at
+                .saturating_duration_since(Instant::now())
+        };
+        // six hours, minus the safety margin
+        assert!(left(&expiring) > Duration::from_secs(5 * 60 * 60));
+        assert!(left(&plain) <= Duration::from_secs(600));
+    }
+
+    #[test]
+    fn disabled_without_capacity() {
+        let cache = ResolverCache::new(0, Duration::from_secs(3600));
+        let url = expiring_url("video", 2);
+        cache.insert(key(&url), resolution(&url));
+        assert!(cache.get(&key(&url)).is_none());
+    }
+
+    #[test]
+    fn invalidates_every_size() {
+        let cache = ResolverCache::new(4, Duration::from_secs(3600));
+        let url = expiring_url("video", 2);
+        for width in [0, 100] {
+            let key = CacheKey {
+                url: url.clone(),
+                width,
+                height: 0,
+                audio_only: false,
+            };
+            cache.insert(key, resolution(&url));
+        }
+        assert_eq!(cache.invalidate(&url), 2);
+        assert_eq!(cache.clear(), 0);
+    }
+
+    #[tokio::test]
+    async fn coalesces_concurrent_misses() {
+        let cache = Arc::new(ResolverCache::new(4, Duration::from_secs(3600)));
+        let resolver = CachingResolver::new(CountingResolver::default(), cache);
+        let url = expiring_url("video", 2);
+
+        let (first, second, third) = futures::join!(
+            resolver.resolve(&url, 0, 0, false),
+            resolver.resolve(&url, 0, 0, false),
+            resolver.resolve(&url, 0, 0, false),
+        );
+        assert_eq!(first.unwrap(), second.unwrap());
+        assert!(third.is_ok());
+        assert_eq!(resolver.inner.calls.load(Ordering::SeqCst), 1);
+        assert!(resolver.pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn resolves_again_after_invalidating() {
+        let cache = Arc::new(ResolverCache::new(4, Duration::from_secs(3600)));
+        let resolver = CachingResolver::new(CountingResolver::default(), cache);
+        let url = expiring_url("video", 2);
+
+        resolver.resolve(&url, 0, 0, false).await.unwrap();
+        resolver.resolve(&url, 0, 0, false).await.unwrap();
+        assert_eq!(resolver.inner.calls.load(Ordering::SeqCst), 1);
+
+        resolver.invalidate(&url);
+        resolver.resolve(&url, 0, 0, false).await.unwrap();
+        assert_eq!(resolver.inner.calls.load(Ordering::SeqCst), 2);
+    }
+}
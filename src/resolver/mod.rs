@@ -0,0 +1,329 @@
+//! turns the url a client asks for into a [`Source`] that can actually be
+//! opened, [`web::stream`](crate::web::stream) only ever talks to a
+//! [`SourceResolver`] so what sits behind it can be swapped out
+
+use std::{collections::HashMap, path::PathBuf};
+
+use futures::{future::BoxFuture, FutureExt};
+use url::Url;
+
+use crate::{
+    cli::Args,
+    metadata::MediaInfo,
+    source::{claim_stdin, is_direct_media, resolve_in_root, Source, SourceError},
+    ytdl::{Extracted, PlaylistEntry, Ytdl, YtdlError},
+};
+
+pub mod cache;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ResolveError {
+    #[error(transparent)]
+    Source(#[from] SourceError),
+    #[error(transparent)]
+    Ytdl(#[from] YtdlError),
+    #[error("don't know how to stream {0}")]
+    Unsupported(Url),
+    #[error("searching isn't supported here")]
+    SearchUnsupported,
+}
+
+/// where a stream comes from, along with the headers that have to be sent to
+/// get at it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolved {
+    pub source: Source,
+    pub http_headers: HashMap<String, String>,
+    /// whatever the resolver found out about it along the way, anything
+    /// missing gets filled in from the container once it's opened
+    pub info: MediaInfo,
+}
+
+impl From<Source> for Resolved {
+    fn from(source: Source) -> Self {
+        Self {
+            source,
+            http_headers: HashMap::new(),
+            info: MediaInfo::default(),
+        }
+    }
+}
+
+/// what a url turned out to be
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution {
+    Media(Box<Resolved>),
+    /// a bunch of urls to play one after another, each one going through the
+    /// resolver again once it's up
+    Playlist(Vec<PlaylistEntry>),
+}
+
+impl From<Resolved> for Resolution {
+    fn from(resolved: Resolved) -> Self {
+        Self::Media(Box::new(resolved))
+    }
+}
+
+impl From<Source> for Resolution {
+    fn from(source: Source) -> Self {
+        Resolved::from(source).into()
+    }
+}
+
+pub trait SourceResolver: Send + Sync {
+    /// works out where to stream `url` from, `width`x`height` being how big
+    /// it's going to be shown so resolvers that get a choice can go for
+    /// something that isn't much bigger than that. with `audio_only` nothing
+    /// gets shown at all, so there's no point in going for any video
+    fn resolve<'a>(
+        &'a self,
+        url: &'a Url,
+        width: u32,
+        height: u32,
+        audio_only: bool,
+    ) -> BoxFuture<'a, Result<Resolution, ResolveError>>;
+
+    /// called when whatever `url` resolved to couldn't be opened, so that
+    /// resolvers holding on to what they resolved don't hand it out again
+    fn invalidate(&self, _url: &Url) {}
+
+    /// looks for things to play that match `query`
+    fn search<'a>(
+        &'a self,
+        _query: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<PlaylistEntry>, ResolveError>> {
+        futures::future::ready(Err(ResolveError::SearchUnsupported)).boxed()
+    }
+}
+
+/// digs the media out of whatever page `url` points at with yt-dlp
+impl SourceResolver for Ytdl {
+    fn resolve<'a>(
+        &'a self,
+        url: &'a Url,
+        width: u32,
+        height: u32,
+        audio_only: bool,
+    ) -> BoxFuture<'a, Result<Resolution, ResolveError>> {
+        async move {
+            match self.get_stream_url(url, width, height, audio_only).await? {
+                Extracted::Media(resolved) => {
+                    let source =
+                        Source::from_resolved(&resolved.urls).ok_or(YtdlError::NoFormats)?;
+                    Ok(Resolved {
+                        source,
+                        http_headers: resolved.http_headers,
+                        info: resolved.info,
+                    }
+                    .into())
+                }
+                Extracted::Playlist(entries) => Ok(Resolution::Playlist(entries)),
+            }
+        }
+        .boxed()
+    }
+
+    fn search<'a>(
+        &'a self,
+        query: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<PlaylistEntry>, ResolveError>> {
+        async move { Ok(Ytdl::search(self, query).await?) }.boxed()
+    }
+}
+
+/// hands http(s) urls straight to ffmpeg
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DirectResolver;
+
+impl SourceResolver for DirectResolver {
+    fn resolve<'a>(
+        &'a self,
+        url: &'a Url,
+        _width: u32,
+        _height: u32,
+        _audio_only: bool,
+    ) -> BoxFuture<'a, Result<Resolution, ResolveError>> {
+        let resolved = match url.scheme() {
+            "http" | "https" => Ok(Source::Direct(url.clone()).into()),
+            _ => Err(ResolveError::Unsupported(url.clone())),
+        };
+        futures::future::ready(resolved).boxed()
+    }
+}
+
+/// serves `file:` urls out of a directory, never letting them out of it
+#[derive(Debug, Clone)]
+pub struct MediaDirResolver {
+    root: PathBuf,
+}
+
+impl MediaDirResolver {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl SourceResolver for MediaDirResolver {
+    fn resolve<'a>(
+        &'a self,
+        url: &'a Url,
+        _width: u32,
+        _height: u32,
+        _audio_only: bool,
+    ) -> BoxFuture<'a, Result<Resolution, ResolveError>> {
+        let resolved = match url.scheme() {
+            "file" => url
+                .to_file_path()
+                .map_err(|_| SourceError::InvalidFileUrl(url.clone()))
+                .and_then(|path| resolve_in_root(&self.root, &path))
+                .map(|path| Source::File(path).into())
+                .map_err(ResolveError::from),
+            _ => Err(ResolveError::Unsupported(url.clone())),
+        };
+        futures::future::ready(resolved).boxed()
+    }
+}
+
+/// hands back whatever it was set up with and nothing else, so streams can
+/// be run without touching the network
+#[derive(Debug, Clone, Default)]
+pub struct MockResolver {
+    sources: HashMap<Url, Resolution>,
+    searches: HashMap<String, Vec<PlaylistEntry>>,
+}
+
+impl MockResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_source(mut self, url: Url, resolved: impl Into<Resolution>) -> Self {
+        self.sources.insert(url, resolved.into());
+        self
+    }
+
+    pub fn with_search(mut self, query: impl Into<String>, results: Vec<PlaylistEntry>) -> Self {
+        self.searches.insert(query.into(), results);
+        self
+    }
+}
+
+impl SourceResolver for MockResolver {
+    fn resolve<'a>(
+        &'a self,
+        url: &'a Url,
+        _width: u32,
+        _height: u32,
+        _audio_only: bool,
+    ) -> BoxFuture<'a, Result<Resolution, ResolveError>> {
+        let resolved = self
+            .sources
+            .get(url)
+            .cloned()
+            .ok_or_else(|| ResolveError::Unsupported(url.clone()));
+        futures::future::ready(resolved).boxed()
+    }
+
+    fn search<'a>(
+        &'a self,
+        query: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<PlaylistEntry>, ResolveError>> {
+        let results = self.searches.get(query).cloned().unwrap_or_default();
+        futures::future::ready(Ok(results)).boxed()
+    }
+}
+
+/// streams whatever is piped into the server from `stdin:`, which only ever
+/// works for one client
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdinResolver;
+
+impl SourceResolver for StdinResolver {
+    fn resolve<'a>(
+        &'a self,
+        url: &'a Url,
+        _width: u32,
+        _height: u32,
+        _audio_only: bool,
+    ) -> BoxFuture<'a, Result<Resolution, ResolveError>> {
+        let resolved = match url.scheme() {
+            "stdin" => claim_stdin()
+                .map(|()| Source::Stdin.into())
+                .map_err(ResolveError::from),
+            _ => Err(ResolveError::Unsupported(url.clone())),
+        };
+        futures::future::ready(resolved).boxed()
+    }
+}
+
+/// what the server uses by default, hands urls over to whichever of the
+/// other resolvers fits them and only goes through yt-dlp when it has to.
+/// local files and stdin are off unless they're turned on
+#[derive(Debug, Clone, Default)]
+pub struct AutoResolver {
+    ytdl: Ytdl,
+    direct: DirectResolver,
+    media_dir: Option<MediaDirResolver>,
+    stdin: Option<StdinResolver>,
+}
+
+impl AutoResolver {
+    pub fn new(ytdl: Ytdl) -> Self {
+        Self {
+            ytdl,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_args(args: &Args) -> Self {
+        let mut resolver = Self::new(Ytdl::from_args(args)).with_stdin(args.stdin);
+        if let Some(root) = &args.media_root {
+            resolver = resolver.with_media_root(root);
+        }
+        resolver
+    }
+
+    /// serves `file:` urls out of `root`
+    pub fn with_media_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.media_dir = Some(MediaDirResolver::new(root));
+        self
+    }
+
+    /// lets a single client stream stdin
+    pub fn with_stdin(mut self, stdin: bool) -> Self {
+        self.stdin = stdin.then_some(StdinResolver);
+        self
+    }
+}
+
+impl SourceResolver for AutoResolver {
+    fn resolve<'a>(
+        &'a self,
+        url: &'a Url,
+        width: u32,
+        height: u32,
+        audio_only: bool,
+    ) -> BoxFuture<'a, Result<Resolution, ResolveError>> {
+        match url.scheme() {
+            "stdin" => match &self.stdin {
+                Some(stdin) => stdin.resolve(url, width, height, audio_only),
+                None => futures::future::ready(Err(SourceError::StdinDisabled.into())).boxed(),
+            },
+            "file" => match &self.media_dir {
+                Some(media_dir) => media_dir.resolve(url, width, height, audio_only),
+                None => futures::future::ready(Err(SourceError::NoMediaRoot.into())).boxed(),
+            },
+            "http" | "https" if is_direct_media(url) => {
+                self.direct.resolve(url, width, height, audio_only)
+            }
+            _ => self.ytdl.resolve(url, width, height, audio_only),
+        }
+    }
+
+    fn search<'a>(
+        &'a self,
+        query: &'a str,
+    ) -> BoxFuture<'a, Result<Vec<PlaylistEntry>, ResolveError>> {
+        SourceResolver::search(&self.ytdl, query)
+    }
+}
@@ -0,0 +1,105 @@
+//! works out where a stream actually comes from, so that anything ffmpeg can
+//! open on its own doesn't have to go through yt-dlp first
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use url::Url;
+
+/// extensions that mark a plain http(s) link as pointing straight at media
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "mp4", "m4v", "mkv", "webm", "mov", "avi", "flv", "ts", "mp3", "m4a", "aac", "ogg", "oga",
+    "opus", "flac", "wav", "m3u8", "m3u",
+];
+
+/// stdin can only be read once, so only a single stream ever gets it
+static STDIN_CLAIMED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, thiserror::Error)]
+pub enum SourceError {
+    #[error("local files are disabled on this server")]
+    NoMediaRoot,
+    #[error("{0} is not inside of the media root")]
+    OutsideMediaRoot(PathBuf),
+    #[error("invalid file url: {0}")]
+    InvalidFileUrl(Url),
+    #[error("streaming from stdin is disabled on this server")]
+    StdinDisabled,
+    #[error("stdin is already being streamed")]
+    StdinClaimed,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// a link straight to something ffmpeg can open as is
+    Direct(Url),
+    /// separate links for video and audio, which is what yt-dlp hands out
+    /// for sites that serve them apart
+    Split { video: Url, audio: Url },
+    /// a file on the server, always somewhere inside of the media root
+    File(PathBuf),
+    /// whatever's being piped into the server
+    Stdin,
+}
+
+impl Source {
+    /// turns whatever urls yt-dlp came up with into a source, yt-dlp lists
+    /// video first when it gives out two of them
+    pub fn from_resolved(urls: &[Url]) -> Option<Self> {
+        match urls {
+            [] => None,
+            [url] => Some(Self::Direct(url.clone())),
+            [video, audio, ..] => Some(Self::Split {
+                video: video.clone(),
+                audio: audio.clone(),
+            }),
+        }
+    }
+
+    /// what to hand over to ffmpeg to open this source, for [`Self::Split`]
+    /// that's the video
+    pub fn ffmpeg_path(&self) -> String {
+        match self {
+            Self::Direct(url) | Self::Split { video: url, .. } => url.to_string(),
+            Self::File(path) => path.to_string_lossy().into_owned(),
+            Self::Stdin => "pipe:0".to_string(),
+        }
+    }
+}
+
+/// whether `url` looks like it points straight at something ffmpeg can open,
+/// going by its extension
+pub fn is_direct_media(url: &Url) -> bool {
+    Path::new(url.path())
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            MEDIA_EXTENSIONS
+                .iter()
+                .any(|media| media.eq_ignore_ascii_case(ext))
+        })
+}
+
+/// hands out stdin, which only works once for the whole server
+pub(crate) fn claim_stdin() -> Result<(), SourceError> {
+    if STDIN_CLAIMED.swap(true, Ordering::SeqCst) {
+        return Err(SourceError::StdinClaimed);
+    }
+    Ok(())
+}
+
+/// treats `path` as relative to `root`, making sure nothing like `..` or a
+/// symlink gets it out of there
+pub(crate) fn resolve_in_root(root: &Path, path: &Path) -> Result<PathBuf, SourceError> {
+    let root = root.canonicalize()?;
+    let relative = path.strip_prefix("/").unwrap_or(path);
+    let resolved = root.join(relative).canonicalize()?;
+    if !resolved.starts_with(&root) {
+        return Err(SourceError::OutsideMediaRoot(path.to_path_buf()));
+    }
+    Ok(resolved)
+}
@@ -1,73 +1,265 @@
-use std::time::Duration;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
 
 use actix_web::HttpRequest;
 use either::Either;
-use ffmpeg_next::format::input;
+use ffmpeg_next::{format::input_with_dictionary, Dictionary};
 use futures::{FutureExt, StreamExt};
 use rand::Rng;
 use serde::Deserialize;
-use ws::{StreamAudioFrame, StreamVideoFrame};
+use ws::{StreamAudioChannel, StreamAudioFrame, StreamCommand, StreamMessage, StreamVideoFrame};
 
 use crate::{
-    decoder::{DecodeError, Decoder},
-    dfpwm::DfpwmEncoder,
-    dimensions::ResolutionHint,
+    cli::Args,
+    codec::{AudioCodec, AudioEncoder},
+    decoder::{
+        chunk::{AudioChunker, DEFAULT_CHUNK_SIZE},
+        rate::FrameRateConverter,
+        DecodeError, Decoder,
+    },
+    dfpwm,
+    dimensions::{ResolutionHint, ScaleFilter},
+    effects::{db_to_linear, Compressor, EffectChain, Gain, LowPass, Normalizer, PreEmphasis},
+    frame::{AudioChannels, AudioFormat, AudioFrame, VideoFrame},
+    hls::{is_hls_url, HlsError, HlsInput},
+    metadata::MediaInfo,
+    pacer::{Pace, Pacer, DEFAULT_MAX_DRIFT},
     palette::Palette,
-    ytdl::get_stream_url,
+    resolver::{cache::ResolverCache, Resolution, Resolved, SourceResolver},
+    source::Source,
+    ytdl::PlaylistEntry,
 };
 
 pub mod ws;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct StreamQuery {
-    url: url::Url,
+    url: Option<url::Url>,
+    /// something to search for instead of a url
+    q: Option<String>,
+    /// whether a search starts playing its first result right away instead
+    /// of waiting for the client to pick one
+    #[serde(default)]
+    autoplay: bool,
+    /// size of the monitor, can be left out in audio only mode
+    #[serde(default)]
     width: u32,
+    #[serde(default)]
     height: u32,
+    /// only sends audio, for computers that only have a speaker. sources
+    /// without any video are played like this regardless
+    #[serde(default)]
+    audio_only: bool,
+    /// frame rate to bring the video down to, anything at or below 0 sends
+    /// every frame
+    #[serde(default = "default_fps")]
+    fps: f64,
+    /// whether dropped frames get blended into the ones that are sent
+    #[serde(default)]
+    blend: bool,
+    /// filter used when shrinking frames down to size
+    #[serde(default)]
+    filter: ScaleFilter,
+    /// where to start playing from, in seconds
+    start: Option<f64>,
+    /// how many samples go into each audio message, anything over
+    /// [`DEFAULT_CHUNK_SIZE`] gets brought down to it
+    #[serde(default = "default_chunk_size")]
+    chunk_size: usize,
+    /// which speakers audio gets split up between
+    #[serde(default)]
+    channels: AudioChannels,
+    /// what audio gets sent as
+    #[serde(default)]
+    codec: AudioCodec,
+    /// brings audio up (or down) to a steady loudness
+    #[serde(default)]
+    normalize: bool,
+    /// compresses anything above this many dB, full scale being 0
+    compress: Option<f32>,
+    /// how hard the compressor squashes things, 4 meaning 4dB over the
+    /// threshold comes out as 1dB over
+    #[serde(default = "default_compress_ratio")]
+    compress_ratio: f32,
+    /// cuts off anything above this many Hz
+    lowpass: Option<f32>,
+    /// pre-emphasis coefficient, somewhere between 0 and 1
+    preemphasis: Option<f32>,
+    /// gain in dB
+    #[serde(default)]
+    gain: f32,
+    /// plain volume multiplier, applied on top of the gain
+    #[serde(default = "default_volume")]
+    volume: f32,
+}
+
+impl StreamQuery {
+    /// the audio processing asked for, which has to be built once for every
+    /// channel
+    fn effect_chain(&self, sample_rate: u32) -> EffectChain {
+        let mut chain = EffectChain::new();
+        if self.normalize {
+            chain = chain.with(Normalizer::new(sample_rate));
+        }
+        if let Some(threshold) = self.compress {
+            chain = chain.with(Compressor::new(threshold, self.compress_ratio, sample_rate));
+        }
+        if let Some(cutoff) = self.lowpass {
+            chain = chain.with(LowPass::new(cutoff, sample_rate));
+        }
+        if let Some(coefficient) = self.preemphasis {
+            chain = chain.with(PreEmphasis::new(coefficient));
+        }
+        if self.gain != 0.0 || self.volume != 1.0 {
+            chain = chain.with(Gain::new(db_to_linear(self.gain) * self.volume));
+        }
+        chain
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct InvalidateQuery {
+    /// url to forget about, everything is forgotten without one
+    url: Option<url::Url>,
+}
+
+fn default_compress_ratio() -> f32 {
+    4.0
+}
+
+fn default_volume() -> f32 {
+    1.0
+}
+
+fn default_chunk_size() -> usize {
+    DEFAULT_CHUNK_SIZE
+}
+
+/// ComputerCraft only runs at 20 ticks per second, so there's no point in
+/// sending more than that by default
+fn default_fps() -> f64 {
+    20.0
+}
+
+/// server wide settings every stream goes by, handed to [`stream`] as app
+/// data next to the resolver
+#[derive(Debug, Clone, Copy)]
+pub struct StreamConfig {
+    /// sample rate audio is sent at, before the codec gets a say
+    pub sample_rate: u32,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: dfpwm::SAMPLE_RATE,
+        }
+    }
+}
+
+impl StreamConfig {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            sample_rate: args.sample_rate,
+        }
+    }
+}
+
+/// drops cached resolutions, for when a url keeps resolving to something
+/// that doesn't work anymore
+pub async fn invalidate_cache(
+    query: actix_web::web::Query<InvalidateQuery>,
+    cache: actix_web::web::Data<ResolverCache>,
+) -> actix_web::HttpResponse {
+    let removed = match &query.url {
+        Some(url) => cache.invalidate(url),
+        None => cache.clear(),
+    };
+    log::info!("dropped {removed} cached resolutions");
+    actix_web::HttpResponse::Ok().json(serde_json::json!({ "removed": removed }))
 }
 
 pub async fn stream(
     req: HttpRequest,
     body: actix_web::web::Payload,
     query: actix_web::web::Query<StreamQuery>,
+    resolver: actix_web::web::Data<dyn SourceResolver>,
+    config: actix_web::web::Data<StreamConfig>,
 ) -> Result<actix_web::HttpResponse, actix_web::Error> {
-    log::debug!("starting stream for {}", &query.url);
+    if query.url.is_none() && query.q.is_none() {
+        return Err(actix_web::error::ErrorBadRequest(
+            "either url or q has to be given",
+        ));
+    }
+    if !query.audio_only && (query.width == 0 || query.height == 0) {
+        return Err(actix_web::error::ErrorBadRequest(
+            "width and height have to be given unless audio_only is set",
+        ));
+    }
+    log::debug!("starting stream for {:?} / {:?}", query.url, query.q);
     let (resp, mut session, mut stream) = actix_ws::handle(&req, body)?;
 
     let (tx, mut rx) = tokio::sync::mpsc::channel(5);
+    let (cmd_tx, cmd_rx) = std::sync::mpsc::channel();
 
     // basically just does all the decoding in regular blocking code and
     // sends it over to the async code via channels (look up to see channel)
+    let resolver = resolver.into_inner();
+    let config = *config.into_inner();
+    let query = query.into_inner();
     tokio::spawn(async move {
-        let url = get_stream_url(&query.url).await;
-        std::thread::spawn(move || {
-            decode_thread(tx, url.first().unwrap(), query.width, query.height)
-        })
+        let (url, cmd_rx) = match (query.url.clone(), query.q.as_deref()) {
+            (Some(url), _) => (url, cmd_rx),
+            (None, Some(q)) => {
+                let picked =
+                    pick_search_result(resolver.as_ref(), &tx, cmd_rx, q, query.autoplay).await;
+                match picked {
+                    Some(picked) => picked,
+                    None => return,
+                }
+            }
+            (None, None) => unreachable!("checked before upgrading"),
+        };
+
+        match resolver
+            .resolve(&url, query.width, query.height, query.audio_only)
+            .await
+        {
+            Ok(Resolution::Media(resolved)) => {
+                play(
+                    resolver.as_ref(),
+                    &url,
+                    tx,
+                    cmd_rx,
+                    *resolved,
+                    query,
+                    config,
+                )
+                .await;
+            }
+            Ok(Resolution::Playlist(entries)) => {
+                play_queue(resolver.as_ref(), tx, cmd_rx, entries, query, config).await;
+            }
+            Err(e) => {
+                log::error!("can't stream {url}: {e}");
+                send_error(&tx, e).await;
+            }
+        }
     });
 
     // receive frames received from sync code and sends it over to client
     tokio::task::spawn_local({
         let mut session = session.clone();
         async move {
-            while let Some(frame) = rx.recv().await {
-                match frame {
-                    Either::Left(image) => {
-                        #[cfg(debug_assertions)]
-                        let json = serde_json::to_string_pretty(&image).unwrap();
-                        #[cfg(not(debug_assertions))]
-                        let json = serde_json::to_string(&image).unwrap();
-                        if session.text(json).await.is_err() {
-                            break;
-                        }
-                    }
-                    Either::Right(audio) => {
-                        #[cfg(debug_assertions)]
-                        let json = serde_json::to_string_pretty(&audio).unwrap();
-                        #[cfg(not(debug_assertions))]
-                        let json = serde_json::to_string(&audio).unwrap();
-                        if session.text(json).await.is_err() {
-                            break;
-                        }
-                    }
+            while let Some(msg) = rx.recv().await {
+                #[cfg(debug_assertions)]
+                let json = serde_json::to_string_pretty(&msg).unwrap();
+                #[cfg(not(debug_assertions))]
+                let json = serde_json::to_string(&msg).unwrap();
+                if session.text(json).await.is_err() {
+                    break;
                 }
             }
 
@@ -91,7 +283,15 @@ pub async fn stream(
                 msg = stream.next().fuse() => {
                     if let Some(Ok(msg)) = msg {
                         match msg {
-                            actix_ws::Message::Text(_) => Ok(()),
+                            actix_ws::Message::Text(text) => {
+                                match serde_json::from_str::<StreamCommand>(&text) {
+                                    Ok(cmd) => {
+                                        let _ = cmd_tx.send(cmd);
+                                    }
+                                    Err(e) => log::debug!("invalid command from client: {e}"),
+                                }
+                                Ok(())
+                            },
                             actix_ws::Message::Binary(_) => Ok(()),
                             actix_ws::Message::Continuation(_) => Ok(()),
                             actix_ws::Message::Ping(ping) => {
@@ -119,78 +319,584 @@ pub async fn stream(
     Ok(resp)
 }
 
-// boy oh boy that's a nice type
-fn decode_thread(
-    tx: tokio::sync::mpsc::Sender<Either<StreamVideoFrame, StreamAudioFrame>>,
+async fn send_error(tx: &tokio::sync::mpsc::Sender<StreamMessage>, error: impl std::fmt::Display) {
+    let _ = tx
+        .send(StreamMessage::Error {
+            message: error.to_string(),
+        })
+        .await;
+}
+
+/// searches for `q` and sends the results over to the client, then waits for
+/// it to pick one of them unless `autoplay` is on, in which case it's always
+/// the first one. hands back the url to play along with the command receiver
+async fn pick_search_result(
+    resolver: &dyn SourceResolver,
+    tx: &tokio::sync::mpsc::Sender<StreamMessage>,
+    commands: std::sync::mpsc::Receiver<StreamCommand>,
+    q: &str,
+    autoplay: bool,
+) -> Option<(url::Url, std::sync::mpsc::Receiver<StreamCommand>)> {
+    let results = match resolver.search(q).await {
+        Ok(results) if results.is_empty() => {
+            send_error(tx, format!("nothing found for {q:?}")).await;
+            return None;
+        }
+        Ok(results) => results,
+        Err(e) => {
+            log::error!("searching for {q:?} failed: {e}");
+            send_error(tx, e).await;
+            return None;
+        }
+    };
+
+    tx.send(StreamMessage::SearchResults {
+        results: results.clone(),
+    })
+    .await
+    .ok()?;
+    if autoplay {
+        return Some((results[0].url.clone(), commands));
+    }
+
+    // the command channel is a blocking one, and this can take as long as
+    // whoever's at the computer takes to make up their mind
+    let count = results.len();
+    let (index, commands) = tokio::task::spawn_blocking(move || loop {
+        match commands.recv() {
+            Ok(StreamCommand::Pick { index }) if index < count => {
+                return Some((index, commands));
+            }
+            Ok(cmd) => log::debug!("ignoring {cmd:?} while waiting for a pick"),
+            // the client left without picking anything
+            Err(_) => return None,
+        }
+    })
+    .await
+    .ok()??;
+
+    Some((results[index].url.clone(), commands))
+}
+
+/// plays `resolved` (which is what `url` resolved to) on a blocking thread
+/// until it runs out or the client leaves, handing the command receiver back
+/// so whatever plays next can keep using it. anything that goes wrong gets
+/// reported to the client, but if decoding panicked there's nothing to hand
+/// back
+async fn play(
+    resolver: &dyn SourceResolver,
     url: &url::Url,
-    width: u32,
-    height: u32,
+    tx: tokio::sync::mpsc::Sender<StreamMessage>,
+    commands: std::sync::mpsc::Receiver<StreamCommand>,
+    resolved: Resolved,
+    query: StreamQuery,
+    config: StreamConfig,
+) -> Option<std::sync::mpsc::Receiver<StreamCommand>> {
+    log::debug!("streaming {url} from {:?}", resolved.source);
+    let decoding = tokio::task::spawn_blocking({
+        let tx = tx.clone();
+        move || {
+            let result = decode_thread(&tx, &commands, &resolved, &query, &config);
+            (commands, result)
+        }
+    });
+    match decoding.await {
+        Ok((commands, Ok(()))) => Some(commands),
+        Ok((commands, Err(e))) => {
+            log::error!("stopped streaming {url}: {e}");
+            // most likely a url that expired or was never any good, so
+            // nobody else should be handed it either
+            if e.is_open_failure() {
+                resolver.invalidate(url);
+            }
+            send_error(&tx, e).await;
+            Some(commands)
+        }
+        Err(e) => {
+            log::error!("decode thread died: {e}");
+            None
+        }
+    }
+}
+
+/// plays every entry one after another, telling the client whenever the next
+/// one starts. entries that fail to resolve are skipped
+async fn play_queue(
+    resolver: &dyn SourceResolver,
+    tx: tokio::sync::mpsc::Sender<StreamMessage>,
+    mut commands: std::sync::mpsc::Receiver<StreamCommand>,
+    entries: Vec<PlaylistEntry>,
+    mut query: StreamQuery,
+    config: StreamConfig,
 ) {
-    let ictx = input(url.as_str()).unwrap();
-    let vid_stream = ictx
-        .streams()
-        .best(ffmpeg_next::media::Type::Video)
-        .unwrap();
-    let aud_stream = ictx
+    let count = entries.len();
+    for (index, entry) in entries.into_iter().enumerate() {
+        if tx.is_closed() {
+            break;
+        }
+
+        let resolved = match resolver
+            .resolve(&entry.url, query.width, query.height, query.audio_only)
+            .await
+        {
+            Ok(Resolution::Media(resolved)) => *resolved,
+            Ok(Resolution::Playlist(_)) => {
+                log::warn!(
+                    "skipping playlist nested inside of a playlist: {}",
+                    entry.url
+                );
+                continue;
+            }
+            Err(e) => {
+                log::warn!("skipping playlist entry {}: {e}", entry.url);
+                continue;
+            }
+        };
+
+        let url = entry.url.clone();
+        let next = StreamMessage::Next {
+            index,
+            count,
+            entry,
+        };
+        if tx.send(next).await.is_err() {
+            break;
+        }
+
+        commands = match play(
+            resolver,
+            &url,
+            tx.clone(),
+            commands,
+            resolved,
+            query.clone(),
+            config,
+        )
+        .await
+        {
+            Some(commands) => commands,
+            None => break,
+        };
+        // only the first entry starts somewhere other than the beginning
+        query.start = None;
+    }
+}
+
+/// options for opening inputs that make ffmpeg send along the headers the
+/// site expects
+fn input_options(http_headers: &HashMap<String, String>) -> Dictionary<'static> {
+    let mut options = Dictionary::new();
+    // ffmpeg wants the user agent on its own, everything else goes into one
+    // big string of headers
+    let mut headers = String::new();
+    for (name, value) in http_headers {
+        if name.eq_ignore_ascii_case("user-agent") {
+            options.set("user_agent", value);
+        } else {
+            headers.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+    if !headers.is_empty() {
+        options.set("headers", &headers);
+    }
+    options
+}
+
+/// why a stream stopped before it ran out
+#[derive(Debug, thiserror::Error)]
+pub enum StreamError {
+    #[error("failed to open the stream: {0}")]
+    Open(ffmpeg_next::Error),
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+    #[error(transparent)]
+    Hls(#[from] HlsError),
+    #[error("nothing in there to play")]
+    NothingToPlay,
+}
+
+impl StreamError {
+    /// whether it never got as far as playing anything because the source
+    /// couldn't be opened
+    fn is_open_failure(&self) -> bool {
+        matches!(self, Self::Open(_) | Self::Hls(_))
+    }
+}
+
+/// decodes and sends off everything in `resolved`, returning early without
+/// an error if the client goes away
+fn decode_thread(
+    tx: &tokio::sync::mpsc::Sender<StreamMessage>,
+    commands: &std::sync::mpsc::Receiver<StreamCommand>,
+    resolved: &Resolved,
+    query: &StreamQuery,
+    config: &StreamConfig,
+) -> Result<(), StreamError> {
+    let Resolved {
+        source,
+        http_headers,
+        info,
+    } = resolved;
+
+    // has to outlive the input context, since that's reading from its pipe
+    let hls = match source {
+        Source::Direct(url) if is_hls_url(url) => {
+            match HlsInput::spawn(
+                url.clone(),
+                query.width,
+                query.height,
+                query.audio_only,
+                http_headers,
+            ) {
+                Ok(hls) => Some(hls),
+                Err(HlsError::Unsupported(what)) => {
+                    log::debug!("leaving HLS stream using {what} to ffmpeg");
+                    None
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        _ => None,
+    };
+    // audio only has no use for a separate video input, so the audio one
+    // takes its place
+    let main_path = match source {
+        Source::Split { video: _, audio } if query.audio_only => audio.to_string(),
+        _ => source.ffmpeg_path(),
+    };
+    let ictx = match &hls {
+        Some(hls) => input_with_dictionary(&hls.ffmpeg_path(), Dictionary::new()),
+        None => input_with_dictionary(&main_path, input_options(http_headers)),
+    }
+    .map_err(StreamError::Open)?;
+    let audio_ictx = match source {
+        Source::Split { video: _, audio } if !query.audio_only => Some(
+            input_with_dictionary(audio.as_str(), input_options(http_headers))
+                .map_err(StreamError::Open)?,
+        ),
+        _ => None,
+    };
+    let vid_stream = match query.audio_only {
+        true => None,
+        false => ictx.streams().best(ffmpeg_next::media::Type::Video),
+    };
+    let aud_stream = audio_ictx
+        .as_ref()
+        .unwrap_or(&ictx)
         .streams()
-        .best(ffmpeg_next::media::Type::Audio)
-        .unwrap();
-    let vid_rate: f64 = vid_stream.rate().into();
+        .best(ffmpeg_next::media::Type::Audio);
+    let vid_rate: Option<f64> = vid_stream.as_ref().map(|stream| stream.rate().into());
+    let has_audio = aud_stream.is_some();
+
+    log::debug!("video frame rate: {:?}", vid_rate);
 
-    log::debug!("video frame rate: {}", vid_rate);
+    let resolution_hint = ResolutionHint::fit(query.width, query.height, const { 2.0 / 3.0 })
+        .with_filter(query.filter);
+    let audio_format =
+        AudioFormat::new(query.codec.sample_rate(config.sample_rate)).with_channels(query.channels);
+    let decoder = match (vid_stream, aud_stream) {
+        (Some(vid_stream), Some(aud_stream)) => {
+            Decoder::new_both(vid_stream, aud_stream, resolution_hint)
+        }
+        (Some(vid_stream), None) => {
+            log::debug!("no audio stream found, only sending video");
+            Decoder::new_video_only(vid_stream, resolution_hint)
+        }
+        (None, Some(aud_stream)) => {
+            log::debug!("no video stream to send, only sending audio");
+            Decoder::new_audio_only(aud_stream)
+        }
+        (None, None) => return Err(StreamError::NothingToPlay),
+    }?
+    .with_audio_format(audio_format);
 
-    let decoder = Decoder::new_video_only(
-        vid_stream,
-        ResolutionHint::fit(width, height, const { 2.0 / 3.0 }),
-    )
-    .unwrap();
+    let dimensions = decoder.output_dimensions();
+    let metadata = StreamMessage::Metadata {
+        info: info.clone().or(MediaInfo::from_input(&ictx)),
+        width: dimensions.map(|(width, _)| width),
+        height: dimensions.map(|(_, height)| height),
+        // the rate converter can only drop frames, never add any
+        fps: vid_rate.map(|rate| {
+            if query.fps > 0.0 {
+                query.fps.min(rate)
+            } else {
+                rate
+            }
+        }),
+        codec: query.codec,
+        sample_rate: audio_format.sample_rate,
+        channels: audio_format.channels.names(),
+    };
+    if tx.blocking_send(metadata).is_err() {
+        return Ok(());
+    }
 
-    let mut decode_iter = decoder.into_frame_iter(ictx);
+    let mut decode_iter = match audio_ictx {
+        Some(audio_ictx) => decoder.into_split_frame_iter(ictx, audio_ictx),
+        None => decoder.into_frame_iter(ictx),
+    };
+    if let Some(start) = query.start {
+        if let Err(e) = decode_iter.seek(start) {
+            log::error!("failed to seek to start offset {start}: {e}");
+        }
+    }
 
-    let mut dfpwm_encoder = DfpwmEncoder::new();
+    // every channel gets its own encoder, since they each carry state over
+    // from one chunk to the next
+    let mut encoders = vec![AudioEncoder::new(query.codec); audio_format.channels.count()];
+    let mut effects: Vec<EffectChain> = (0..audio_format.channels.count())
+        .map(|_| query.effect_chain(audio_format.sample_rate))
+        .collect();
+    let mut chunker = AudioChunker::new(query.chunk_size, audio_format.sample_rate);
+    // a chunk of audio only comes out once all of it has been decoded, so
+    // video is held back by that much to get the audio out ahead of it
+    let lead = if has_audio {
+        chunker.chunk_duration()
+    } else {
+        0.0
+    };
+    // chunks of audio are that far apart without anything having jumped
+    let mut pacer = Pacer::new()
+        .with_max_drift(DEFAULT_MAX_DRIFT + Duration::from_secs_f64(chunker.chunk_duration()));
+    let mut held_video = VecDeque::new();
+    let mut rate_converter =
+        (query.fps > 0.0).then(|| FrameRateConverter::new(query.fps).with_blending(query.blend));
+    // set once the HLS follower says timestamps are going to jump
+    let mut new_timeline_coming = false;
+    let mut last_timestamp: Option<f64> = None;
     loop {
-        match decode_iter.next() {
-            Some(Ok(Either::Left(video_frame))) => {
-                let palette = Palette::new(16, &video_frame);
-
-                let mut lines: Vec<String> = (0..video_frame.height())
-                    .map(|_| String::with_capacity(video_frame.width() as usize))
-                    .collect();
+        while let Ok(cmd) = commands.try_recv() {
+            match cmd {
+                StreamCommand::Pick { .. } => log::debug!("nothing to pick from right now"),
+                StreamCommand::Seek { position } => match decode_iter.seek(position) {
+                    Ok(()) => {
+                        reset_timeline(
+                            &mut pacer,
+                            &mut chunker,
+                            &mut held_video,
+                            &mut effects,
+                            rate_converter.as_mut(),
+                        );
+                        last_timestamp = None;
+                    }
+                    Err(e) => log::error!("failed to seek to {position}: {e}"),
+                },
+            }
+        }
+        if hls.as_ref().is_some_and(HlsInput::take_discontinuity) {
+            new_timeline_coming = true;
+        }
 
-                for (i, pal_idx) in palette.index_iter(&video_frame).enumerate() {
-                    let line = i / video_frame.width() as usize;
-                    lines[line].push(char::from_digit(pal_idx as u32, 16).unwrap());
+        let next = decode_iter.next();
+        if let Some(Ok(frame)) = &next {
+            let timestamp = frame
+                .as_ref()
+                .either(VideoFrame::timestamp, AudioFrame::timestamp);
+            // whatever was still in the pipe comes out before the jump, so
+            // the timeline only starts over once the timestamps actually do
+            let jumped = last_timestamp
+                .is_some_and(|last| (timestamp - last).abs() > DEFAULT_MAX_DRIFT.as_secs_f64());
+            if new_timeline_coming && jumped {
+                log::debug!("timestamps jumped to {timestamp:.3}s, starting over");
+                new_timeline_coming = false;
+                let sent = send_leftovers(
+                    tx,
+                    &mut encoders,
+                    audio_format.channels,
+                    &mut pacer,
+                    &mut chunker,
+                    &mut held_video,
+                    lead,
+                );
+                if !sent {
+                    break;
                 }
+                reset_timeline(
+                    &mut pacer,
+                    &mut chunker,
+                    &mut held_video,
+                    &mut effects,
+                    rate_converter.as_mut(),
+                );
+            }
+            last_timestamp = Some(timestamp);
+        }
+
+        match next {
+            Some(Ok(Either::Left(video_frame))) => {
+                let video_frame = match rate_converter.as_mut() {
+                    Some(converter) => match converter.push(video_frame) {
+                        Some(frame) => frame,
+                        None => continue,
+                    },
+                    None => video_frame,
+                };
 
-                if tx
-                    .blocking_send(Either::Left(StreamVideoFrame {
-                        palette: palette
-                            .into_iter()
-                            .map(|pix| [pix.0[0], pix.0[1], pix.0[2]])
-                            .collect(),
-                        rows: lines,
-                    }))
-                    .is_err()
-                {
+                // decoding has to stay a chunk ahead of the clock for the
+                // audio to make it out in time, so frames only get waited on
+                // once they're that far behind
+                let until = video_frame.timestamp() - lead;
+                held_video.push_back(video_frame);
+                if !send_video_until(tx, &mut held_video, &mut pacer, lead, until) {
                     break;
                 }
             }
-            Some(Ok(Either::Right(audio_frame))) => {
-                if tx
-                    .blocking_send(Either::Right(StreamAudioFrame {
-                        samples: dfpwm_encoder.encode(audio_frame.samples().iter().copied()),
-                    }))
-                    .is_err()
-                {
+            Some(Ok(Either::Right(mut audio_frame))) => {
+                for (chain, samples) in effects.iter_mut().zip(audio_frame.iter_mut()) {
+                    chain.process(samples);
+                }
+                let sent = chunker.push(&audio_frame).into_iter().all(|chunk| {
+                    send_video_until(tx, &mut held_video, &mut pacer, lead, chunk.timestamp())
+                        && send_audio_chunk(
+                            tx,
+                            &mut encoders,
+                            audio_format.channels,
+                            &mut pacer,
+                            &chunk,
+                        )
+                });
+                if !sent {
                     break;
                 }
             }
             Some(Err(DecodeError::NoFramesYet)) => (),
-            Some(Err(e)) => {
-                log::error!("{e}");
+            Some(Err(e)) => return Err(e.into()),
+            None => {
+                send_leftovers(
+                    tx,
+                    &mut encoders,
+                    audio_format.channels,
+                    &mut pacer,
+                    &mut chunker,
+                    &mut held_video,
+                    lead,
+                );
                 break;
             }
-            None => break,
         }
     }
+    Ok(())
+}
+
+/// forgets about where the stream was at, for when it jumps somewhere else
+fn reset_timeline(
+    pacer: &mut Pacer,
+    chunker: &mut AudioChunker,
+    held_video: &mut VecDeque<VideoFrame>,
+    effects: &mut [EffectChain],
+    rate_converter: Option<&mut FrameRateConverter>,
+) {
+    pacer.reset();
+    chunker.reset();
+    held_video.clear();
+    effects.iter_mut().for_each(EffectChain::reset);
+    if let Some(converter) = rate_converter {
+        converter.reset();
+    }
+}
+
+/// sends off whatever audio and video is still being held onto, returning
+/// whether the client is still around
+fn send_leftovers(
+    tx: &tokio::sync::mpsc::Sender<StreamMessage>,
+    encoders: &mut [AudioEncoder],
+    channels: AudioChannels,
+    pacer: &mut Pacer,
+    chunker: &mut AudioChunker,
+    held_video: &mut VecDeque<VideoFrame>,
+    lead: f64,
+) -> bool {
+    if let Some(chunk) = chunker.flush() {
+        if !send_video_until(tx, held_video, pacer, lead, chunk.timestamp())
+            || !send_audio_chunk(tx, encoders, channels, pacer, &chunk)
+        {
+            return false;
+        }
+    }
+    send_video_until(tx, held_video, pacer, lead, f64::INFINITY)
+}
+
+/// sends off every held back frame that's due by `until`, each one `lead`
+/// seconds after its own timestamp, returning whether the client is still
+/// around
+fn send_video_until(
+    tx: &tokio::sync::mpsc::Sender<StreamMessage>,
+    held: &mut VecDeque<VideoFrame>,
+    pacer: &mut Pacer,
+    lead: f64,
+    until: f64,
+) -> bool {
+    while held
+        .front()
+        .is_some_and(|frame| frame.timestamp() + lead <= until)
+    {
+        let Some(frame) = held.pop_front() else {
+            break;
+        };
+        // no point in working out a palette for a frame the client would
+        // only get after it should've been shown
+        if pacer.wait(frame.timestamp() + lead) == Pace::Late {
+            log::trace!("dropping late frame at {:.3}s", frame.timestamp());
+            continue;
+        }
+        if tx
+            .blocking_send(StreamMessage::Video(render(&frame)))
+            .is_err()
+        {
+            return false;
+        }
+    }
+    true
+}
+
+/// turns a frame into 16 colors and the rows of palette indices using them
+fn render(frame: &VideoFrame) -> StreamVideoFrame {
+    let palette = Palette::new(16, frame);
+
+    let mut lines: Vec<String> = (0..frame.height())
+        .map(|_| String::with_capacity(frame.width() as usize))
+        .collect();
+
+    for (i, pal_idx) in palette.index_iter(frame).enumerate() {
+        let line = i / frame.width() as usize;
+        lines[line].push(char::from_digit(pal_idx as u32, 16).unwrap());
+    }
+
+    StreamVideoFrame {
+        timestamp: frame.timestamp(),
+        palette: palette
+            .into_iter()
+            .map(|pix| [pix.0[0], pix.0[1], pix.0[2]])
+            .collect(),
+        rows: lines,
+    }
+}
+
+/// encodes and sends off a chunk of audio, returning whether the client is
+/// still around
+fn send_audio_chunk(
+    tx: &tokio::sync::mpsc::Sender<StreamMessage>,
+    encoders: &mut [AudioEncoder],
+    channels: AudioChannels,
+    pacer: &mut Pacer,
+    chunk: &AudioFrame,
+) -> bool {
+    // audio is never dropped, skipping samples sounds way worse than a frame
+    // or two going missing
+    pacer.wait(chunk.timestamp());
+    let channels = channels
+        .names()
+        .iter()
+        .zip(encoders.iter_mut())
+        .zip(chunk.channels())
+        .map(|((&name, encoder), samples)| StreamAudioChannel {
+            name,
+            samples: encoder.encode(samples),
+        })
+        .collect();
+    tx.blocking_send(StreamMessage::Audio(StreamAudioFrame {
+        timestamp: chunk.timestamp(),
+        channels,
+    }))
+    .is_ok()
 }
@@ -1,12 +1,81 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec::{AudioCodec, EncodedAudio},
+    metadata::MediaInfo,
+    ytdl::PlaylistEntry,
+};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct StreamVideoFrame {
+    /// when the frame is due, in seconds
+    pub timestamp: f64,
     pub palette: Vec<[u8; 3]>,
     pub rows: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct StreamAudioFrame {
-    pub samples: Vec<u8>,
+    /// when the first sample is due, in seconds
+    pub timestamp: f64,
+    pub channels: Vec<StreamAudioChannel>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamAudioChannel {
+    /// which speaker this is for, like `left` or `center`
+    pub name: &'static str,
+    pub samples: EncodedAudio,
+}
+
+/// everything that gets sent over to the client, tagged with a `type` field so
+/// it can tell them apart
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamMessage {
+    /// what's about to be played, always sent before any frames
+    Metadata {
+        #[serde(flatten)]
+        info: MediaInfo,
+        /// size of the video frames that are going to be sent, these are all
+        /// null when only audio is
+        width: Option<u32>,
+        height: Option<u32>,
+        fps: Option<f64>,
+        /// what audio is sent as
+        codec: AudioCodec,
+        /// what the audio has to be played back at
+        sample_rate: u32,
+        /// names of the audio channels, in the order they're sent in
+        channels: &'static [&'static str],
+    },
+    Video(StreamVideoFrame),
+    Audio(StreamAudioFrame),
+    /// the next entry of a playlist is starting, `index` counting from 0
+    Next {
+        index: usize,
+        count: usize,
+        #[serde(flatten)]
+        entry: PlaylistEntry,
+    },
+    /// what a search came up with, the client picks one of them by its
+    /// index unless the first one is already playing
+    SearchResults {
+        results: Vec<PlaylistEntry>,
+    },
+    /// something went wrong and whatever was playing can't go on, a playlist
+    /// still moves on to its next entry after this
+    Error {
+        message: String,
+    },
+}
+
+/// messages the client can send while a stream is running
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamCommand {
+    /// jump to `position` seconds into the stream
+    Seek { position: f64 },
+    /// play the search result at `index`
+    Pick { index: usize },
 }
@@ -1,24 +1,309 @@
-use std::{io::BufRead, process::Stdio};
+use std::{collections::HashMap, path::PathBuf, process::Stdio, time::Duration};
 
+use serde::{Deserialize, Serialize};
 use url::Url;
 
-pub async fn get_stream_url(url: &Url) -> Vec<Url> {
-    let out = tokio::process::Command::new("/usr/bin/env")
-        .arg("yt-dlp")
-        .arg("-g")
-        .arg(url.as_str())
-        .stdout(Stdio::piped())
-        .spawn()
-        .unwrap()
-        .wait_with_output()
-        .await
-        .expect("failed to execute yt-dlp");
-    log::debug!("{}", String::from_utf8_lossy(&out.stdout));
-    out.stdout.lines().next();
-    let stdout = String::from_utf8_lossy(&out.stdout);
-
-    stdout
-        .lines()
-        .filter_map(|l| Url::try_from(l).ok())
+use crate::{
+    cli::Args,
+    metadata::{Chapter, MediaInfo},
+};
+
+/// cookie attributes yt-dlp tacks on that aren't part of the cookie itself
+const COOKIE_ATTRIBUTES: &[&str] = &[
+    "domain", "path", "expires", "max-age", "secure", "httponly", "samesite",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum YtdlError {
+    #[error("yt-dlp could not be found at {0:?}")]
+    NotInstalled(PathBuf),
+    #[error("failed to run yt-dlp: {0}")]
+    Io(std::io::Error),
+    #[error("yt-dlp did not finish within {0:?}")]
+    Timeout(Duration),
+    #[error("yt-dlp failed: {0}")]
+    ExtractorFailed(String),
+    #[error("failed to parse yt-dlp output: {0}")]
+    InvalidOutput(#[from] serde_json::Error),
+    #[error("yt-dlp found no formats to play")]
+    NoFormats,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct YtdlFormat {
+    url: Url,
+    #[serde(default)]
+    http_headers: HashMap<String, String>,
+    cookies: Option<String>,
+}
+
+/// an entry of a flat playlist, which is all yt-dlp knows about a video
+/// before it's actually extracted
+#[derive(Debug, Clone, Deserialize)]
+struct YtdlEntry {
+    /// some extractors leave this out for entries that can't be played
+    url: Option<Url>,
+    title: Option<String>,
+    duration: Option<f64>,
+}
+
+/// the parts of `yt-dlp -J`'s output we care about
+#[derive(Debug, Clone, Deserialize)]
+struct YtdlInfo {
+    /// `"playlist"` for playlists, anything else is a single video
+    #[serde(rename = "_type")]
+    kind: Option<String>,
+    /// only there for playlists
+    entries: Option<Vec<YtdlEntry>>,
+    /// only there when a single format was picked
+    url: Option<Url>,
+    #[serde(default)]
+    http_headers: HashMap<String, String>,
+    cookies: Option<String>,
+    /// something like `"160 - 256x144 (144p)+249 - audio only (tiny)"`
+    format: Option<String>,
+    title: Option<String>,
+    duration: Option<f64>,
+    uploader: Option<String>,
+    /// null rather than empty for videos without any
+    chapters: Option<Vec<Chapter>>,
+    /// only there when separate video and audio formats were picked
+    requested_formats: Option<Vec<YtdlFormat>>,
+}
+
+/// media urls yt-dlp came up with, along with the headers the site expects
+/// them to be requested with
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedUrls {
+    pub urls: Vec<Url>,
+    pub http_headers: HashMap<String, String>,
+    pub info: MediaInfo,
+}
+
+/// a video in a playlist, which still has to be resolved on its own once
+/// it's its turn to play
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    pub url: Url,
+    pub title: Option<String>,
+    /// in seconds
+    pub duration: Option<f64>,
+}
+
+/// what yt-dlp found at a url
+#[derive(Debug, Clone)]
+pub enum Extracted {
+    Media(ResolvedUrls),
+    Playlist(Vec<PlaylistEntry>),
+}
+
+/// turns yt-dlp's cookie string (which looks a lot like a bunch of
+/// `Set-Cookie` headers mashed together) into a `Cookie` header
+fn cookie_header(cookies: &str) -> String {
+    cookies
+        .split(';')
+        .map(str::trim)
+        .filter(|part| {
+            let name = part.split('=').next().unwrap_or_default();
+            part.contains('=')
+                && !COOKIE_ATTRIBUTES
+                    .iter()
+                    .any(|attr| attr.eq_ignore_ascii_case(name))
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// runs yt-dlp, which binary and what extra arguments it gets are up to
+/// whoever sets it up, so it can just as well be a script that fakes it
+#[derive(Debug, Clone)]
+pub struct Ytdl {
+    binary: PathBuf,
+    extra_args: Vec<String>,
+    timeout: Duration,
+    /// what goes in front of search terms, like `ytsearch5`
+    search_prefix: String,
+}
+
+impl Default for Ytdl {
+    fn default() -> Self {
+        Self::new("yt-dlp")
+    }
+}
+
+impl Ytdl {
+    pub fn new(binary: impl Into<PathBuf>) -> Self {
+        Self {
+            binary: binary.into(),
+            extra_args: Vec::new(),
+            timeout: Duration::from_secs(30),
+            search_prefix: "ytsearch5".to_string(),
+        }
+    }
+
+    pub fn from_args(args: &Args) -> Self {
+        Self::new(&args.ytdl_path)
+            .with_extra_args(args.ytdl_args.clone())
+            .with_timeout(Duration::from_secs(args.ytdl_timeout))
+            .with_search_prefix(args.search_prefix.clone())
+    }
+
+    /// arguments passed to every invocation, before any of our own
+    pub fn with_extra_args(mut self, extra_args: Vec<String>) -> Self {
+        self.extra_args = extra_args;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// which of yt-dlp's search extractors to use, optionally with how many
+    /// results it should come up with, like `ytsearch5` or `scsearch`
+    pub fn with_search_prefix(mut self, search_prefix: String) -> Self {
+        self.search_prefix = search_prefix;
+        self
+    }
+
+    /// runs yt-dlp with `args` followed by `target`, returning whatever it
+    /// wrote to stdout
+    async fn run(&self, args: &[&str], target: &str) -> Result<Vec<u8>, YtdlError> {
+        let child = tokio::process::Command::new(&self.binary)
+            .args(&self.extra_args)
+            .args(args)
+            .arg(target)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => YtdlError::NotInstalled(self.binary.clone()),
+                _ => YtdlError::Io(e),
+            })?;
+
+        let out = tokio::time::timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| YtdlError::Timeout(self.timeout))?
+            .map_err(YtdlError::Io)?;
+
+        if !out.status.success() {
+            // yt-dlp puts the actual reason on the last line, after a bunch of
+            // warnings
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            let reason = stderr
+                .lines()
+                .rev()
+                .find(|line| !line.trim().is_empty())
+                .unwrap_or("exited without saying why");
+            return Err(YtdlError::ExtractorFailed(reason.trim().to_string()));
+        }
+
+        Ok(out.stdout)
+    }
+
+    /// yt-dlp's format selector for the smallest video that's still at least
+    /// `width`x`height`, along with the cheapest audio there is. if nothing
+    /// is big enough the best there is has to do, since that's the closest.
+    /// with `audio_only` it's just the cheapest audio, or the cheapest
+    /// format that has any if the site doesn't serve it separately
+    pub fn format_selector(width: u32, height: u32, audio_only: bool) -> String {
+        if audio_only {
+            return "wa/w".to_string();
+        }
+        let big_enough = format!("[width>={width}][height>={height}]");
+        format!("wv{big_enough}+wa/w{big_enough}/bv*+wa/b")
+    }
+
+    /// resolves `url` to something that can be shown at `width`x`height`
+    /// without pulling a lot more than that, or to just the audio with
+    /// `audio_only`. playlists only get their entries listed so that they
+    /// don't take forever
+    pub async fn get_stream_url(
+        &self,
+        url: &Url,
+        width: u32,
+        height: u32,
+        audio_only: bool,
+    ) -> Result<Extracted, YtdlError> {
+        let format = Self::format_selector(width, height, audio_only);
+        let stdout = self
+            .run(&["-J", "--flat-playlist", "-f", &format], url.as_str())
+            .await?;
+        let info: YtdlInfo = serde_json::from_slice(&stdout)?;
+
+        if info.kind.as_deref() == Some("playlist") {
+            let entries = playlist_entries(info.entries);
+            if entries.is_empty() {
+                return Err(YtdlError::NoFormats);
+            }
+            log::info!("{url} is a playlist with {} entries", entries.len());
+            return Ok(Extracted::Playlist(entries));
+        }
+
+        log::info!(
+            "yt-dlp picked format {} for {url}",
+            info.format.as_deref().unwrap_or("unknown")
+        );
+
+        let formats = match (info.requested_formats, info.url) {
+            (Some(formats), _) => formats,
+            (None, Some(url)) => vec![YtdlFormat {
+                url,
+                http_headers: info.http_headers,
+                cookies: info.cookies,
+            }],
+            (None, None) => Vec::new(),
+        };
+        if formats.is_empty() {
+            return Err(YtdlError::NoFormats);
+        }
+
+        // every format gets the same headers anyways
+        let mut http_headers = formats[0].http_headers.clone();
+        if let Some(cookies) = formats[0].cookies.as_deref() {
+            http_headers.insert("Cookie".to_string(), cookie_header(cookies));
+        }
+
+        let urls: Vec<Url> = formats.into_iter().map(|format| format.url).collect();
+        log::debug!("yt-dlp resolved {url} to {urls:?}");
+
+        let info = MediaInfo {
+            title: info.title,
+            duration: info.duration,
+            uploader: info.uploader,
+            chapters: info.chapters.unwrap_or_default(),
+        };
+
+        Ok(Extracted::Media(ResolvedUrls {
+            urls,
+            http_headers,
+            info,
+        }))
+    }
+
+    /// searches for `query` with the configured search prefix, which yt-dlp
+    /// hands back as a playlist of whatever it found
+    pub async fn search(&self, query: &str) -> Result<Vec<PlaylistEntry>, YtdlError> {
+        let target = format!("{}:{query}", self.search_prefix);
+        let stdout = self.run(&["-J", "--flat-playlist"], &target).await?;
+        let info: YtdlInfo = serde_json::from_slice(&stdout)?;
+        Ok(playlist_entries(info.entries))
+    }
+}
+
+/// keeps the entries of a flat playlist that can actually be played
+fn playlist_entries(entries: Option<Vec<YtdlEntry>>) -> Vec<PlaylistEntry> {
+    entries
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|entry| {
+            Some(PlaylistEntry {
+                url: entry.url?,
+                title: entry.title,
+                duration: entry.duration,
+            })
+        })
         .collect()
 }
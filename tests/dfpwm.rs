@@ -0,0 +1,168 @@
+use std::f32::consts::TAU;
+
+use cc_streaming::dfpwm::{DfpwmDecoder, DfpwmEncoder, SAMPLE_RATE};
+
+/// the decoder's filters take a little while to settle in, so comparisons
+/// skip over the start
+const WARMUP: usize = 1000;
+
+/// a sine that sweeps linearly from `from` to `to` Hz over a second
+fn sweep(from: f32, to: f32, amplitude: f32) -> Vec<f32> {
+    let rate = SAMPLE_RATE as f32;
+    let mut phase = 0.0f32;
+    (0..SAMPLE_RATE)
+        .map(|i| {
+            let freq = from + (to - from) * i as f32 / rate;
+            phase = (phase + TAU * freq / rate) % TAU;
+            amplitude * phase.sin()
+        })
+        .collect()
+}
+
+/// a millisecond of 1kHz at half volume, in the -128..=127 levels the lua
+/// side works with
+const TONE: [i8; 64] = [
+    0, 8, 16, 24, 32, 39, 45, 50, 55, 59, 61, 63, 64, 63, 61, 59, 55, 50, 45, 39, 32, 24, 16, 8, 0,
+    -8, -16, -24, -32, -39, -45, -50, -55, -59, -61, -63, -64, -63, -61, -59, -55, -50, -45, -39,
+    -32, -24, -16, -8, 0, 8, 16, 24, 32, 39, 45, 50, 55, 59, 61, 63, 64, 63, 61, 59,
+];
+
+/// what `cc.audio.dfpwm`'s encoder turns [`TONE`] into
+const TONE_ENCODED: [u8; 8] = [254, 255, 31, 0, 0, 252, 255, 191];
+
+/// what `cc.audio.dfpwm`'s decoder turns [`TONE_ENCODED`] back into
+const TONE_DECODED: [i8; 64] = [
+    -1, 0, 1, 2, 3, 4, 5, 6, 8, 10, 12, 14, 16, 18, 20, 22, 24, 26, 28, 30, 32, 31, 28, 24, 20, 16,
+    12, 8, 4, 0, -4, -8, -12, -16, -20, -24, -28, -32, -36, -40, -44, -48, -47, -41, -34, -27, -20,
+    -13, -6, 0, 6, 12, 18, 24, 29, 34, 39, 44, 49, 54, 58, 62, 61, 58,
+];
+
+/// the start of a 200Hz sine, played right after [`PINNED`] samples of full
+/// volume so the predictor's strength is as high as it goes
+const RAMP: [i8; 64] = [
+    0, 3, 6, 9, 12, 15, 18, 21, 24, 27, 30, 32, 35, 38, 41, 44, 46, 49, 52, 55, 57, 60, 62, 65, 67,
+    70, 72, 74, 76, 79, 81, 83, 85, 87, 89, 91, 92, 94, 96, 97, 99, 100, 102, 103, 104, 106, 107,
+    108, 109, 110, 110, 111, 112, 112, 113, 113, 114, 114, 114, 114, 114, 114, 114, 114,
+];
+const PINNED: usize = 1200;
+
+/// what `cc.audio.dfpwm` encodes [`RAMP`] as, after all the full volume
+/// bytes
+const RAMP_ENCODED: [u8; 8] = [170, 170, 170, 170, 170, 170, 170, 182];
+
+/// what `cc.audio.dfpwm` decodes [`RAMP_ENCODED`] as
+const RAMP_DECODED: [i8; 64] = [
+    58, 26, 12, 5, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, -1, 0, 0, 0, -1, 0, 0, 0, -1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, -1, 68, 34,
+    15, 76, 38, 17,
+];
+
+fn from_levels(levels: &[i8]) -> impl Iterator<Item = f32> + '_ {
+    levels.iter().map(|&level| level as f32 / 127.0)
+}
+
+fn to_levels(samples: &[f32]) -> Vec<i8> {
+    samples
+        .iter()
+        .map(|sample| (sample * 127.0).round() as i8)
+        .collect()
+}
+
+fn round_trip(samples: &[f32]) -> Vec<f32> {
+    let encoded = DfpwmEncoder::new().encode(samples.iter().copied());
+    DfpwmDecoder::new().decode(&encoded)
+}
+
+/// signal to noise ratio of `decoded` against `original`, in dB
+fn snr(original: &[f32], decoded: &[f32]) -> f32 {
+    let (signal, noise) = original
+        .iter()
+        .zip(decoded)
+        .skip(WARMUP)
+        .fold((0.0, 0.0), |(signal, noise), (a, b)| {
+            (signal + a * a, noise + (a - b) * (a - b))
+        });
+    10.0 * (signal / noise).log10()
+}
+
+#[test]
+fn encodes_8_samples_per_byte() {
+    let encoded = DfpwmEncoder::new().encode(sweep(100.0, 1000.0, 0.5));
+    assert_eq!(encoded.len(), SAMPLE_RATE as usize / 8);
+    assert_eq!(
+        DfpwmDecoder::new().decode(&encoded).len(),
+        SAMPLE_RATE as usize
+    );
+}
+
+#[test]
+fn low_sweep_round_trips() {
+    let original = sweep(50.0, 1000.0, 0.5);
+    let decoded = round_trip(&original);
+    let snr = snr(&original, &decoded);
+    assert!(snr > 15.0, "snr was {snr}dB");
+}
+
+#[test]
+fn wide_sweep_round_trips() {
+    let original = sweep(100.0, 4000.0, 0.5);
+    let decoded = round_trip(&original);
+    let snr = snr(&original, &decoded);
+    assert!(snr > 8.0, "snr was {snr}dB");
+}
+
+#[test]
+fn silence_stays_quiet() {
+    let decoded = round_trip(&[0.0; 4800]);
+    let loudest = decoded[WARMUP..]
+        .iter()
+        .fold(0.0f32, |loudest, sample| loudest.max(sample.abs()));
+    assert!(loudest < 0.05, "loudest sample was {loudest}");
+}
+
+#[test]
+fn decoding_in_chunks_matches_decoding_at_once() {
+    let encoded = DfpwmEncoder::new().encode(sweep(100.0, 2000.0, 0.8));
+
+    let whole = DfpwmDecoder::new().decode(&encoded);
+    let mut decoder = DfpwmDecoder::new();
+    let chunked: Vec<f32> = encoded
+        .chunks(1024)
+        .flat_map(|chunk| decoder.decode(chunk))
+        .collect();
+
+    assert_eq!(whole, chunked);
+}
+
+#[test]
+fn encoding_in_chunks_matches_encoding_at_once() {
+    let samples = sweep(100.0, 2000.0, 0.8);
+
+    let whole = DfpwmEncoder::new().encode(samples.iter().copied());
+    let mut encoder = DfpwmEncoder::new();
+    let chunked: Vec<u8> = samples
+        .chunks(1024)
+        .flat_map(|chunk| encoder.encode(chunk.iter().copied()))
+        .collect();
+
+    assert_eq!(whole, chunked);
+}
+
+#[test]
+fn matches_cc_tweaked() {
+    let encoded = DfpwmEncoder::new().encode(from_levels(&TONE));
+    assert_eq!(encoded, TONE_ENCODED);
+    let decoded = DfpwmDecoder::new().decode(&encoded);
+    assert_eq!(to_levels(&decoded), TONE_DECODED);
+}
+
+#[test]
+fn matches_cc_tweaked_at_full_strength() {
+    let samples = std::iter::repeat_n(1.0, PINNED).chain(from_levels(&RAMP));
+    let encoded = DfpwmEncoder::new().encode(samples);
+    assert!(encoded[..PINNED / 8].iter().all(|&byte| byte == 0xff));
+    assert_eq!(encoded[PINNED / 8..], RAMP_ENCODED);
+
+    let decoded = DfpwmDecoder::new().decode(&encoded);
+    assert_eq!(to_levels(&decoded[PINNED..]), RAMP_DECODED);
+}
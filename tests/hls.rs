@@ -0,0 +1,301 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use cc_streaming::hls::{check_supported, first_new_segment, select_variant, HlsError, HlsInput};
+use m3u8_rs::{MasterPlaylist, MediaPlaylist};
+use url::Url;
+
+fn fixture_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/hls")
+        .join(name)
+}
+
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(fixture_path(name)).unwrap()
+}
+
+fn master(name: &str) -> MasterPlaylist {
+    m3u8_rs::parse_master_playlist_res(&fixture(name)).unwrap()
+}
+
+fn media(name: &str) -> MediaPlaylist {
+    m3u8_rs::parse_media_playlist_res(&fixture(name)).unwrap()
+}
+
+/// serves the fixtures over http on a random port, `live.m3u8` going through
+/// `live` one request at a time and staying on the last one. segments are
+/// just their own name followed by a newline
+fn serve(live: &'static [&'static str]) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let reloads = Arc::new(AtomicUsize::new(0));
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            // the rest of the request doesn't matter
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+
+            let path = request_line.split(' ').nth(1).unwrap_or("/");
+            let name = path.trim_start_matches('/');
+            let body = if name == "live.m3u8" {
+                let reload = reloads.fetch_add(1, Ordering::SeqCst);
+                Some(fixture(live[reload.min(live.len() - 1)]))
+            } else if let Some(segment) = name.strip_suffix(".ts") {
+                Some(format!("{segment}\n").into_bytes())
+            } else {
+                std::fs::read(fixture_path(name)).ok()
+            };
+
+            let response = match body {
+                Some(body) => {
+                    let mut response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .into_bytes();
+                    response.extend(body);
+                    response
+                }
+                None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_vec(),
+            };
+            let _ = stream.write_all(&response);
+        }
+    });
+
+    Url::parse(&format!("http://{addr}/")).unwrap()
+}
+
+/// everything the follower writes out until the stream ends
+fn read_all(input: &mut HlsInput) -> Vec<String> {
+    let mut out = String::new();
+    input.read_to_string(&mut out).unwrap();
+    out.lines().map(str::to_string).collect()
+}
+
+#[test]
+fn picks_smallest_covering_variant() {
+    let master = master("master.m3u8");
+    assert_eq!(
+        select_variant(&master, 100, 60, false).unwrap().uri,
+        "low.m3u8"
+    );
+    assert_eq!(
+        select_variant(&master, 300, 200, false).unwrap().uri,
+        "mid.m3u8"
+    );
+    assert_eq!(
+        select_variant(&master, 640, 360, false).unwrap().uri,
+        "mid.m3u8"
+    );
+}
+
+#[test]
+fn picks_biggest_variant_when_none_cover() {
+    // the i-frame stream is the only one that's big enough, but it's no use
+    let master = master("master.m3u8");
+    assert_eq!(
+        select_variant(&master, 1920, 1080, false).unwrap().uri,
+        "high.m3u8"
+    );
+    assert_eq!(
+        select_variant(&master, 4000, 4000, false).unwrap().uri,
+        "high.m3u8"
+    );
+}
+
+#[test]
+fn picks_cheapest_variant_without_resolutions() {
+    let master = master("master_no_resolution.m3u8");
+    assert_eq!(
+        select_variant(&master, 100, 100, false).unwrap().uri,
+        "a.m3u8"
+    );
+}
+
+#[test]
+fn picks_audio_variant_in_audio_only_mode() {
+    let master = master("master.m3u8");
+    assert_eq!(
+        select_variant(&master, 0, 0, true).unwrap().uri,
+        "audio.m3u8"
+    );
+}
+
+#[test]
+fn picks_cheapest_variant_without_audio_only_ones() {
+    let master = m3u8_rs::parse_master_playlist_res(
+        b"#EXTM3U\n\
+        #EXT-X-STREAM-INF:BANDWIDTH=900000,RESOLUTION=640x360\n\
+        b.m3u8\n\
+        #EXT-X-STREAM-INF:BANDWIDTH=300000,RESOLUTION=256x144\n\
+        a.m3u8\n",
+    )
+    .unwrap();
+    assert_eq!(select_variant(&master, 0, 0, true).unwrap().uri, "a.m3u8");
+}
+
+#[test]
+fn starts_vod_from_beginning() {
+    assert_eq!(first_new_segment(&media("vod.m3u8"), None), 0);
+}
+
+#[test]
+fn starts_live_at_edge() {
+    // 5 segments, so it starts 3 from the end
+    assert_eq!(first_new_segment(&media("live_0.m3u8"), None), 2);
+}
+
+#[test]
+fn continues_after_last_played() {
+    let playlist = media("live_1.m3u8");
+    // sequence 5 is the fourth segment in there
+    assert_eq!(first_new_segment(&playlist, Some(4)), 3);
+    // nothing new yet
+    assert_eq!(first_new_segment(&playlist, Some(6)), 5);
+    // fell behind, so it starts from the oldest one left
+    assert_eq!(first_new_segment(&playlist, Some(0)), 0);
+}
+
+#[test]
+fn starts_over_when_sequence_goes_backwards() {
+    assert_eq!(first_new_segment(&media("live_0.m3u8"), Some(1000)), 2);
+    assert_eq!(first_new_segment(&media("vod.m3u8"), Some(1000)), 0);
+}
+
+#[test]
+fn waits_out_stale_playlists() {
+    // one segment behind what was already played, so there's nothing new
+    // rather than a restart
+    let playlist = media("live_stale.m3u8");
+    assert!(first_new_segment(&playlist, Some(6)) >= playlist.segments.len());
+    assert!(first_new_segment(&playlist, Some(5)) >= playlist.segments.len());
+}
+
+#[test]
+fn leaves_encryption_and_byte_ranges_to_ffmpeg() {
+    assert!(check_supported(&media("vod.m3u8")).is_ok());
+    assert!(matches!(
+        check_supported(&media("encrypted.m3u8")),
+        Err(HlsError::Unsupported(_))
+    ));
+    assert!(matches!(
+        check_supported(&media("byterange.m3u8")),
+        Err(HlsError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn refuses_unsupported_playlists_before_following() {
+    let base = serve(&[]);
+    for name in ["encrypted.m3u8", "byterange.m3u8"] {
+        let url = base.join(name).unwrap();
+        let result = HlsInput::spawn(url, 0, 0, false, &HashMap::new());
+        assert!(matches!(result, Err(HlsError::Unsupported(_))));
+    }
+}
+
+#[test]
+fn follows_variant_from_master() {
+    let base = serve(&[]);
+    let input = HlsInput::spawn(
+        base.join("master.m3u8").unwrap(),
+        300,
+        200,
+        false,
+        &HashMap::new(),
+    );
+    assert_eq!(read_all(&mut input.unwrap()), ["mid0", "mid1"]);
+}
+
+#[test]
+fn skips_stale_playlist_without_replaying() {
+    let base = serve(&[
+        "live_0.m3u8",
+        "live_1.m3u8",
+        "live_stale.m3u8",
+        "live_2.m3u8",
+    ]);
+    let input = HlsInput::spawn(
+        base.join("live.m3u8").unwrap(),
+        0,
+        0,
+        false,
+        &HashMap::new(),
+    );
+    assert_eq!(
+        read_all(&mut input.unwrap()),
+        ["live2", "live3", "live4", "live5", "live6", "live7"]
+    );
+}
+
+#[test]
+fn follows_live_playlist_from_edge() {
+    let base = serve(&["live_0.m3u8", "live_1.m3u8", "live_2.m3u8"]);
+    let input = HlsInput::spawn(
+        base.join("live.m3u8").unwrap(),
+        0,
+        0,
+        false,
+        &HashMap::new(),
+    );
+    assert_eq!(
+        read_all(&mut input.unwrap()),
+        ["live2", "live3", "live4", "live5", "live6", "live7"]
+    );
+}
+
+#[test]
+fn plays_through_without_discontinuities() {
+    let base = serve(&[]);
+    let mut input =
+        HlsInput::spawn(base.join("vod.m3u8").unwrap(), 0, 0, false, &HashMap::new()).unwrap();
+    read_all(&mut input);
+    assert!(!input.take_discontinuity());
+}
+
+#[test]
+fn signals_discontinuities() {
+    let base = serve(&[]);
+    let url = base.join("discontinuity.m3u8").unwrap();
+    let mut input = HlsInput::spawn(url, 0, 0, false, &HashMap::new()).unwrap();
+    assert_eq!(read_all(&mut input), ["show0", "show1", "ad0"]);
+    assert!(input.take_discontinuity());
+    // only once
+    assert!(!input.take_discontinuity());
+}
+
+#[test]
+fn signals_restarts() {
+    let base = serve(&["before_restart.m3u8", "after_restart.m3u8"]);
+    let url = base.join("live.m3u8").unwrap();
+    let mut input = HlsInput::spawn(url, 0, 0, false, &HashMap::new()).unwrap();
+    assert_eq!(
+        read_all(&mut input),
+        [
+            "before102",
+            "before103",
+            "before104",
+            "after0",
+            "after1",
+            "after2"
+        ]
+    );
+    assert!(input.take_discontinuity());
+}
@@ -0,0 +1,77 @@
+use std::path::Path;
+
+use cc_streaming::{
+    resolver::{AutoResolver, Resolution, ResolveError, SourceResolver},
+    source::{Source, SourceError},
+    ytdl::Ytdl,
+};
+use url::Url;
+
+fn fixtures() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"))
+}
+
+/// a resolver that can't reach yt-dlp, so nothing here touches the network
+fn resolver() -> AutoResolver {
+    AutoResolver::new(Ytdl::new(fixtures().join("no-yt-dlp")))
+}
+
+async fn resolve(resolver: &AutoResolver, url: &str) -> Result<Resolution, ResolveError> {
+    resolver
+        .resolve(&Url::parse(url).unwrap(), 0, 0, false)
+        .await
+}
+
+#[tokio::test]
+async fn files_need_media_root() {
+    let result = resolve(&resolver(), "file:///tone.wav").await;
+    assert!(matches!(
+        result,
+        Err(ResolveError::Source(SourceError::NoMediaRoot))
+    ));
+}
+
+#[tokio::test]
+async fn files_come_from_media_root() {
+    let resolver = resolver().with_media_root(fixtures());
+    let Resolution::Media(resolved) = resolve(&resolver, "file:///tone.wav").await.unwrap() else {
+        panic!("expected a single file");
+    };
+    let expected = fixtures().join("tone.wav").canonicalize().unwrap();
+    assert_eq!(resolved.source, Source::File(expected));
+}
+
+#[tokio::test]
+async fn files_stay_inside_media_root() {
+    // the url parser would get rid of a plain `..`
+    let resolver = resolver().with_media_root(fixtures().join("hls"));
+    let result = resolve(&resolver, "file:///..%2Ftone.wav").await;
+    assert!(matches!(
+        result,
+        Err(ResolveError::Source(SourceError::OutsideMediaRoot(_)))
+    ));
+}
+
+#[tokio::test]
+async fn stdin_needs_turning_on() {
+    let result = resolve(&resolver(), "stdin:").await;
+    assert!(matches!(
+        result,
+        Err(ResolveError::Source(SourceError::StdinDisabled))
+    ));
+}
+
+#[tokio::test]
+async fn media_links_skip_ytdl() {
+    let url = "https://example.com/video.mp4";
+    let Resolution::Media(resolved) = resolve(&resolver(), url).await.unwrap() else {
+        panic!("expected a single video");
+    };
+    assert_eq!(resolved.source, Source::Direct(Url::parse(url).unwrap()));
+}
+
+#[tokio::test]
+async fn pages_go_through_ytdl() {
+    let result = resolve(&resolver(), "https://example.com/watch?v=abc").await;
+    assert!(matches!(result, Err(ResolveError::Ytdl(_))));
+}
@@ -0,0 +1,175 @@
+use std::{
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use cc_streaming::{
+    resolver::{MockResolver, SourceResolver},
+    source::Source,
+    web::{stream, StreamConfig},
+    ytdl::PlaylistEntry,
+};
+use serde_json::Value;
+use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+use url::Url;
+
+type Client = WebSocket<MaybeTlsStream<TcpStream>>;
+
+fn tone() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tone.wav")
+}
+
+fn tone_url() -> Url {
+    Url::parse("https://example.com/tone").unwrap()
+}
+
+/// runs the server on a random port in the background, with `resolver`
+/// standing in for the real one
+fn start(resolver: impl SourceResolver + 'static) -> SocketAddr {
+    ffmpeg_next::init().unwrap();
+    let resolver: Arc<dyn SourceResolver> = Arc::new(resolver);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    std::thread::spawn(move || {
+        actix_web::rt::System::new().block_on(async move {
+            actix_web::HttpServer::new(move || {
+                actix_web::App::new()
+                    .app_data(actix_web::web::Data::from(resolver.clone()))
+                    .app_data(actix_web::web::Data::new(StreamConfig::default()))
+                    .route("/stream", actix_web::web::get().to(stream))
+            })
+            .workers(1)
+            .listen(listener)
+            .unwrap()
+            .run()
+            .await
+        })
+    });
+
+    addr
+}
+
+fn connect(addr: SocketAddr, query: &str) -> Client {
+    let (client, _) = tungstenite::connect(format!("ws://{addr}/stream?{query}")).unwrap();
+    client
+}
+
+/// the next message the server sends, or `None` once it hangs up
+fn next(client: &mut Client) -> Option<Value> {
+    loop {
+        match client.read() {
+            Ok(Message::Text(text)) => return Some(serde_json::from_str(&text).unwrap()),
+            Ok(Message::Close(_)) | Err(_) => return None,
+            Ok(_) => continue,
+        }
+    }
+}
+
+fn encoded(url: &Url) -> String {
+    url::form_urlencoded::byte_serialize(url.as_str().as_bytes()).collect()
+}
+
+#[test]
+fn streams_mocked_file() {
+    let addr = start(MockResolver::new().with_source(tone_url(), Source::File(tone())));
+    let mut client = connect(
+        addr,
+        &format!(
+            "url={}&audio_only=true&chunk_size=4096",
+            encoded(&tone_url())
+        ),
+    );
+
+    let metadata = next(&mut client).unwrap();
+    assert_eq!(metadata["type"], "metadata");
+    assert_eq!(metadata["width"], Value::Null);
+    assert_eq!(metadata["channels"], serde_json::json!(["mono"]));
+    let sample_rate = metadata["sample_rate"].as_f64().unwrap();
+
+    let mut samples = 0;
+    let mut last_timestamp = -1.0;
+    while let Some(message) = next(&mut client) {
+        assert_eq!(message["type"], "audio");
+        let timestamp = message["timestamp"].as_f64().unwrap();
+        assert!(timestamp > last_timestamp);
+        last_timestamp = timestamp;
+
+        let channel = &message["channels"][0];
+        assert_eq!(channel["name"], "mono");
+        samples += channel["samples"].as_array().unwrap().len() * 8;
+    }
+
+    // half a second of audio, give or take the resampler's padding
+    let seconds = samples as f64 / sample_rate;
+    assert!((seconds - 0.5).abs() < 0.05, "got {seconds}s of audio");
+}
+
+#[test]
+fn reports_unresolvable_url() {
+    let addr = start(MockResolver::new());
+    let mut client = connect(
+        addr,
+        &format!("url={}&audio_only=true", encoded(&tone_url())),
+    );
+
+    let message = next(&mut client).unwrap();
+    assert_eq!(message["type"], "error");
+    assert!(next(&mut client).is_none());
+}
+
+#[test]
+fn reports_missing_file() {
+    let missing = tone().with_file_name("missing.wav");
+    let addr = start(MockResolver::new().with_source(tone_url(), Source::File(missing)));
+    let mut client = connect(
+        addr,
+        &format!("url={}&audio_only=true", encoded(&tone_url())),
+    );
+
+    let message = next(&mut client).unwrap();
+    assert_eq!(message["type"], "error");
+}
+
+#[test]
+fn plays_picked_search_result() {
+    let results = vec![
+        PlaylistEntry {
+            url: Url::parse("https://example.com/nothing").unwrap(),
+            title: Some("nothing".to_string()),
+            duration: None,
+        },
+        PlaylistEntry {
+            url: tone_url(),
+            title: Some("tone".to_string()),
+            duration: Some(0.5),
+        },
+    ];
+    let addr = start(
+        MockResolver::new()
+            .with_source(tone_url(), Source::File(tone()))
+            .with_search("tone", results),
+    );
+    let mut client = connect(addr, "q=tone&audio_only=true");
+
+    let message = next(&mut client).unwrap();
+    assert_eq!(message["type"], "search_results");
+    assert_eq!(message["results"][1]["title"], "tone");
+
+    client
+        .send(Message::Text(r#"{"type": "pick", "index": 1}"#.into()))
+        .unwrap();
+    let message = next(&mut client).unwrap();
+    assert_eq!(message["type"], "metadata");
+}
+
+#[test]
+fn needs_size_unless_audio_only() {
+    let addr = start(MockResolver::new().with_source(tone_url(), Source::File(tone())));
+    let url = format!("ws://{addr}/stream?url={}&width=51", encoded(&tone_url()));
+    match tungstenite::connect(url) {
+        Err(tungstenite::Error::Http(resp)) => assert_eq!(resp.status(), 400),
+        other => panic!("expected a bad request, got {other:?}"),
+    }
+}
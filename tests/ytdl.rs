@@ -0,0 +1,106 @@
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use cc_streaming::ytdl::{Extracted, Ytdl, YtdlError};
+use url::Url;
+
+const FAILING: &str = r#"#!/bin/sh
+echo "WARNING: [youtube] falling back to something" >&2
+echo "ERROR: [youtube] abc: Video unavailable" >&2
+exit 1
+"#;
+
+const HANGING: &str = "#!/bin/sh\nexec sleep 10\n";
+
+/// prints out what `yt-dlp -J` would for a video with separate formats
+const SPLIT: &str = r#"#!/bin/sh
+cat <<EOF
+{
+    "title": "some video",
+    "duration": 12.5,
+    "format": "160+249",
+    "requested_formats": [
+        {"url": "https://example.com/video", "http_headers": {"User-Agent": "fake"}},
+        {"url": "https://example.com/audio", "http_headers": {"User-Agent": "fake"}}
+    ]
+}
+EOF
+"#;
+
+/// writes out the fake yt-dlps once, before any test gets to run them, since
+/// running a script another thread still has open for writing fails
+fn scripts() -> &'static Path {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let dir = std::env::temp_dir().join(format!("cc_streaming-ytdl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, script) in [("failing", FAILING), ("hanging", HANGING), ("split", SPLIT)] {
+            let path = dir.join(name);
+            std::fs::write(&path, script).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        dir
+    })
+}
+
+fn video_url() -> Url {
+    Url::parse("https://www.youtube.com/watch?v=abc").unwrap()
+}
+
+#[tokio::test]
+async fn missing_binary_is_not_installed() {
+    let binary = scripts().join("does-not-exist");
+    let result = Ytdl::new(&binary)
+        .get_stream_url(&video_url(), 0, 0, false)
+        .await;
+    assert!(matches!(result, Err(YtdlError::NotInstalled(path)) if path == binary));
+}
+
+#[tokio::test]
+async fn reports_last_line_of_stderr() {
+    let ytdl = Ytdl::new(scripts().join("failing"));
+    match ytdl.get_stream_url(&video_url(), 0, 0, false).await {
+        Err(YtdlError::ExtractorFailed(reason)) => {
+            assert_eq!(reason, "ERROR: [youtube] abc: Video unavailable")
+        }
+        other => panic!("expected the extractor to fail, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn gives_up_after_timeout() {
+    let ytdl = Ytdl::new(scripts().join("hanging")).with_timeout(Duration::from_millis(200));
+    let started = std::time::Instant::now();
+    let result = ytdl.search("anything").await;
+    assert!(matches!(result, Err(YtdlError::Timeout(_))));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn resolves_split_formats() {
+    let ytdl = Ytdl::new(scripts().join("split"));
+    let Extracted::Media(resolved) = ytdl
+        .get_stream_url(&video_url(), 0, 0, false)
+        .await
+        .unwrap()
+    else {
+        panic!("expected a single video");
+    };
+    assert_eq!(resolved.urls.len(), 2);
+    assert_eq!(resolved.urls[1].as_str(), "https://example.com/audio");
+    assert_eq!(resolved.http_headers["User-Agent"], "fake");
+    assert_eq!(resolved.info.title.as_deref(), Some("some video"));
+    assert_eq!(resolved.info.duration, Some(12.5));
+}
+
+#[test]
+fn audio_only_skips_video() {
+    assert!(Ytdl::format_selector(640, 360, false).contains("[width>=640][height>=360]"));
+    let selector = Ytdl::format_selector(640, 360, true);
+    assert!(selector.starts_with("wa"));
+    assert!(!selector.contains("wv") && !selector.contains("bv"));
+}